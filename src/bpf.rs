@@ -1,15 +1,132 @@
 //! bpf-profile bpf module.
 
-use crate::config::{ProgramCounter, PADDING};
+use crate::config::{Cost, ProgramCounter, PADDING};
+
+/// Number of registers recorded on every trace line (r0..r10).
+pub const NUMBER_OF_REGISTERS: usize = 11;
+
+/// Represents values of the register file.
+pub type Registers = [u64; NUMBER_OF_REGISTERS];
+
+/// Size of one BPF instruction in bytes.
+pub const INSTRUCTION_SIZE: u64 = 8;
+
+/// Name of the function where programs start.
+pub const ENTRYPOINT: &str = "entrypoint";
+
+/// Virtual address of the program region (and size of every memory region).
+pub const MM_PROGRAM_START: u64 = 0x1_0000_0000;
+
+/// Virtual address of the stack region.
+pub const MM_STACK_START: u64 = 0x2_0000_0000;
+
+/// Virtual address of the heap region.
+pub const MM_HEAP_START: u64 = 0x3_0000_0000;
+
+/// Virtual address of the input region (serialized accounts and instruction data).
+pub const MM_INPUT_START: u64 = 0x4_0000_0000;
+
+/// Size of the stack frame of a function in bytes (Solana 1.6 and later).
+pub const STACK_FRAME_SIZE: u64 = 4096;
+
+/// Maximum depth of calls including the entrypoint (Solana 1.6 and later).
+pub const MAX_CALL_DEPTH: usize = 64;
+
+/// Maximum number of instructions of a program: 10 MiB of code,
+/// the maximum size of account data (Solana 1.6 and later).
+pub const MAX_INSTRUCTIONS: usize = 10 * 1024 * 1024 / INSTRUCTION_SIZE as usize;
+
+/// Represents the kind of a memory access of an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    /// Load from the memory to a register.
+    Load,
+    /// Store of a register or an immediate value to the memory.
+    Store,
+}
+
+/// Represents a region of the virtual memory of the VM.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Region {
+    /// Code and read-only data of the program.
+    Program,
+    /// Stack frames of functions.
+    Stack,
+    /// Heap of the program.
+    Heap,
+    /// Serialized accounts and instruction data.
+    Input,
+}
+
+impl Region {
+    /// All regions in order of their addresses.
+    pub const ALL: [Region; 4] = [Region::Program, Region::Stack, Region::Heap, Region::Input];
+
+    /// Returns the region which contains the address (if any).
+    pub fn of(address: u64) -> Option<Region> {
+        let region = match address {
+            _ if address < MM_PROGRAM_START => return None,
+            _ if address < MM_STACK_START => Region::Program,
+            _ if address < MM_HEAP_START => Region::Stack,
+            _ if address < MM_INPUT_START => Region::Heap,
+            _ if address < MM_INPUT_START + MM_PROGRAM_START => Region::Input,
+            _ => return None,
+        };
+        Some(region)
+    }
+
+    /// Returns name of the region.
+    pub fn name(self) -> &'static str {
+        match self {
+            Region::Program => "Program",
+            Region::Stack => "Stack",
+            Region::Heap => "Heap",
+            Region::Input => "Input",
+        }
+    }
+}
+
+/// Names of syscalls provided by the runtime of Solana 1.6 and compute units
+/// they consume (base costs of its compute budget).
+/// Syscalls of later versions (e.g. `sol_log_data`) can be added by the config file.
+pub const SYSCALLS: &[(&str, Cost)] = &[
+    ("abort", 0),
+    ("sol_panic_", 0),
+    ("sol_log_", 100),
+    ("sol_log_64_", 100),
+    ("sol_log_compute_units_", 100),
+    ("sol_log_pubkey", 100),
+    ("sol_create_program_address", 1500),
+    ("sol_sha256", 85),
+    ("sol_keccak256", 85),
+    ("sol_get_clock_sysvar", 100),
+    ("sol_get_epoch_schedule_sysvar", 100),
+    ("sol_get_fees_sysvar", 100),
+    ("sol_get_rent_sysvar", 100),
+    ("sol_memcpy_", 10),
+    ("sol_memmove_", 10),
+    ("sol_memcmp_", 10),
+    ("sol_memset_", 10),
+    ("sol_invoke_signed_c", 1000),
+    ("sol_invoke_signed_rust", 1000),
+    ("sol_alloc_free_", 0),
+];
+
+/// Classes of instruction opcodes.
+pub const CLASSES: &[&str] = &["ld", "ldx", "st", "stx", "alu", "jmp", "alu64"];
+
+/// Categories of instructions by their effect.
+pub const CATEGORIES: &[&str] = &["Loads", "Stores", "ALU", "Branches", "Calls", "MulDiv"];
 
 /// Represents BPF instruction (call or another).
 #[derive(Debug, Default, Clone, PartialEq, PartialOrd)]
 pub struct Instruction {
     pc: ProgramCounter,
     text: String,
+    regs: Registers,
 }
 
-use crate::config::Address;
+use crate::config::{Address, Config, Map};
 use crate::error::{Error, Result};
 use lazy_static::lazy_static;
 use regex::Regex;
@@ -17,22 +134,32 @@ use regex::Regex;
 impl Instruction {
     /// Creates new instance of Instruction.
     pub fn new(pc: ProgramCounter, text: String) -> Self {
-        Instruction { pc, text }
+        Instruction {
+            pc,
+            text,
+            regs: Registers::default(),
+        }
     }
 
     /// Parses the input string and creates corresponding instruction if possible.
-    pub fn parse(s: &str) -> Result<Self> {
+    /// Returns `TraceSkipped` if the string is not an instruction of the trace.
+    pub fn parse(s: &str, lc: usize) -> Result<Self> {
         lazy_static! {
-            static ref TRACE_INSTRUCTION: Regex =
-                Regex::new(r"\d+\s+\[.+\]\s+(\d+):\s+(.+)").expect("Invalid regex");
+            static ref TRACE_INSTRUCTION: Regex = Regex::new(
+                r"\d+\s+\[((?:[[:xdigit:]]{1,16},\s*){10}[[:xdigit:]]{1,16})\]\s+(\d+):\s+(.+)"
+            )
+            .expect("Invalid regex");
         }
 
         if let Some(caps) = TRACE_INSTRUCTION.captures(s) {
-            let pc = caps[1]
-                .parse::<ProgramCounter>()
-                .expect("Cannot parse program counter");
-            let text = caps[2].trim().to_string();
-            return Ok(Instruction { pc, text });
+            let error = || Error::TraceParsing(s.trim().to_string(), lc);
+            let mut regs = Registers::default();
+            for (r, value) in regs.iter_mut().zip(caps[1].split(',')) {
+                *r = u64::from_str_radix(value.trim(), 16).map_err(|_| error())?;
+            }
+            let pc = caps[2].parse::<ProgramCounter>().map_err(|_| error())?;
+            let text = caps[3].trim().to_string();
+            return Ok(Instruction { pc, text, regs });
         }
 
         Err(Error::TraceSkipped)
@@ -53,11 +180,100 @@ impl Instruction {
         self.text.clone()
     }
 
+    /// Returns values of all registers before execution of the instruction.
+    pub fn registers(&self) -> &Registers {
+        &self.regs
+    }
+
+    /// Returns value of the register with index `r` (0..=10).
+    pub fn register(&self, r: usize) -> u64 {
+        self.regs[r]
+    }
+
+    /// Checks if both instructions have the same pc and text, ignoring registers.
+    pub fn has_same_code(&self, other: &Instruction) -> bool {
+        self.pc == other.pc && self.text == other.text
+    }
+
+    /// Returns the BPF class of the instruction opcode (one of `CLASSES`),
+    /// recognized by the mnemonic.
+    pub fn class(&self) -> &'static str {
+        let mnemonic = self.text.split_whitespace().next().unwrap_or_default();
+        if mnemonic == "lddw" {
+            "ld"
+        } else if mnemonic.starts_with("ldx") {
+            "ldx"
+        } else if mnemonic.starts_with("stx") {
+            "stx"
+        } else if mnemonic.starts_with("st") {
+            "st"
+        } else if mnemonic.starts_with('j') || self.is_call() || self.is_exit() {
+            "jmp"
+        } else if mnemonic.ends_with("32")
+            || mnemonic.starts_with("le")
+            || mnemonic.starts_with("be")
+        {
+            "alu"
+        } else {
+            "alu64"
+        }
+    }
+
+    /// Returns the category of the instruction (one of `CATEGORIES`),
+    /// recognized by the mnemonic. Exits are counted together with calls.
+    pub fn category(&self) -> &'static str {
+        let mnemonic = self.text.split_whitespace().next().unwrap_or_default();
+        match self.class() {
+            "ld" | "ldx" => "Loads",
+            "st" | "stx" => "Stores",
+            "jmp" if self.is_call() || self.is_exit() => "Calls",
+            "jmp" => "Branches",
+            _ if ["mul", "div", "mod"]
+                .iter()
+                .any(|op| mnemonic.starts_with(op)) =>
+            {
+                "MulDiv"
+            }
+            _ => "ALU",
+        }
+    }
+
+    /// Returns the kind, the virtual address and the size in bytes of memory
+    /// accessed by the instruction (if it is a load or a store).
+    pub fn memory_access(&self) -> Option<(Access, u64, usize)> {
+        lazy_static! {
+            static ref ACCESS: Regex =
+                Regex::new(r"^(ldx|stx|st)(dw|w|h|b)\s.*\[r(\d+)([+-])0x([[:xdigit:]]+)\]")
+                    .expect("Invalid regex");
+        }
+        let caps = ACCESS.captures(&self.text)?;
+        let access = match &caps[1] {
+            "ldx" => Access::Load,
+            _ => Access::Store,
+        };
+        let size = match &caps[2] {
+            "dw" => 8,
+            "w" => 4,
+            "h" => 2,
+            _ => 1,
+        };
+        let base = *self.regs.get(caps[3].parse::<usize>().ok()?)?;
+        // Offsets are 16-bit signed numbers, printed as unsigned ones
+        let offset = u16::from_str_radix(&caps[5], 16).ok()? as i16 as i64;
+        let offset = if &caps[4] == "-" { -offset } else { offset };
+        Some((access, base.wrapping_add(offset as u64), size))
+    }
+
     /// Checks if the instruction is a call of function.
     pub fn is_call(&self) -> bool {
         self.text.starts_with("call")
     }
 
+    /// Checks if the instruction is an indirect call (via register).
+    pub fn is_indirect_call(&self) -> bool {
+        self.text.starts_with("callx")
+    }
+
     /// Checks if the instruction is exit of function.
     pub fn is_exit(&self) -> bool {
         self.text == "exit"
@@ -76,6 +292,7 @@ impl Instruction {
     }
 
     /// Returns address of a call target or error if instruction is not a call.
+    /// The target of an indirect call is the value of its register.
     pub fn extract_call_target(&self, lc: usize) -> Result<Address> {
         if !self.is_call() {
             return Err(Error::TraceNotCall(self.text(), lc));
@@ -84,13 +301,66 @@ impl Instruction {
         let _ = pair
             .next()
             .ok_or_else(|| Error::TraceParsing(self.text(), lc))?;
-        let address = pair
+        let operand = pair
             .next()
             .ok_or_else(|| Error::TraceParsing(self.text(), lc))?;
-        Ok(hex_str_to_address(address))
+        let parse =
+            |s: &str| hex_str_to_address(s).ok_or_else(|| Error::TraceParsing(self.text(), lc));
+        if !self.is_indirect_call() {
+            return parse(operand);
+        }
+        // => "callx 0x4" in traces or "callx r4" in dumps
+        let r = parse(operand.trim_start_matches('r'))?;
+        if r >= NUMBER_OF_REGISTERS {
+            return Err(Error::TraceParsing(self.text(), lc));
+        }
+        Ok(self.register(r) as Address)
+    }
+
+    /// Returns name of the syscall if the instruction calls a known one.
+    /// Calls of syscalls use the hash of the name instead of an address.
+    /// Syscalls unknown to Solana 1.6 are looked up in the settings.
+    pub fn syscall(&self, config: &Config) -> Option<String> {
+        if !self.is_call() || self.is_indirect_call() {
+            return None;
+        }
+        let operand = self.text.split_whitespace().nth(1)?;
+        let hash = u32::from_str_radix(operand.trim_start_matches("0x"), 16).ok()?;
+        lazy_static! {
+            static ref BUILTIN: Map<u32, &'static str> = SYSCALLS
+                .iter()
+                .map(|(name, _)| (hash_symbol_name(name.as_bytes()), *name))
+                .collect();
+        }
+        match BUILTIN.get(&hash) {
+            Some(name) => Some(name.to_string()),
+            None => config.syscall(hash),
+        }
+    }
+
+    /// Returns address of a call target, given the first instruction of the callee.
+    /// Indirect calls with a register value outside of the program region
+    /// are resolved by the pc of the callee's first instruction.
+    pub fn call_target(&self, first: &Instruction, lc: usize) -> Result<Address> {
+        let address = self.extract_call_target(lc)?;
+        if self.is_indirect_call() && !is_program_address(address) {
+            return Ok(indirect_call_address(first.pc()));
+        }
+        Ok(address)
     }
 }
 
+/// Checks if an address belongs to the program region.
+fn is_program_address(address: Address) -> bool {
+    let address = address as u64;
+    (MM_PROGRAM_START..2 * MM_PROGRAM_START).contains(&address)
+}
+
+/// Returns virtual address of the instruction with given pc in the program region.
+fn indirect_call_address(pc: ProgramCounter) -> Address {
+    (MM_PROGRAM_START + pc as u64 * INSTRUCTION_SIZE) as Address
+}
+
 use std::fmt;
 
 impl fmt::Display for Instruction {
@@ -103,8 +373,122 @@ impl fmt::Display for Instruction {
     }
 }
 
+/// Returns the murmur3 (32 bits, zero seed) hash of a symbol name,
+/// the way the loader identifies syscalls.
+pub fn hash_symbol_name(name: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+    let mix = |k: u32| k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+
+    let mut h = 0_u32;
+    let mut chunks = name.chunks_exact(4);
+    for chunk in &mut chunks {
+        h ^= mix(u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]));
+        h = h.rotate_left(13).wrapping_mul(5).wrapping_add(0xe654_6b64);
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        let k = tail.iter().rev().fold(0_u32, |k, b| k << 8 | *b as u32);
+        h ^= mix(k);
+    }
+
+    h ^= name.len() as u32;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^ h >> 16
+}
+
 /// Converts hex number string representation to integer Address.
-fn hex_str_to_address(s: &str) -> Address {
+fn hex_str_to_address(s: &str) -> Option<Address> {
     let a = s.trim_start_matches("0x");
-    Address::from_str_radix(a, 16).expect("Invalid address")
+    Address::from_str_radix(a, 16).ok()
+}
+
+/// Decodes the instruction (8 bytes, or 16 bytes of `lddw`) into text
+/// in the format of the trace. Returns number of bytes used.
+pub fn disassemble(bytes: &[u8]) -> (String, usize) {
+    if bytes.len() < INSTRUCTION_SIZE as usize {
+        return ("unknown".into(), bytes.len());
+    }
+    let opc = bytes[0];
+    let dst = bytes[1] & 0x0f;
+    let src = bytes[1] >> 4;
+    let off = i16::from_le_bytes([bytes[2], bytes[3]]);
+    let imm = i32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+
+    let class = opc & 0x07;
+    let size = match opc & 0x18 {
+        0x00 => "w",
+        0x08 => "h",
+        0x10 => "b",
+        _ => "dw",
+    };
+    let text = match class {
+        // LD: only the wide immediate load is supported by the VM
+        0x00 if opc == 0x18 && bytes.len() >= 16 => {
+            let high = u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]);
+            let imm = (imm as u32 as u64) | (high as u64) << 32;
+            return (format!("lddw r{}, {:#x}", dst, imm), 16);
+        }
+        0x01 => format!("ldx{} r{}, [r{}+{:#x}]", size, dst, src, off),
+        0x02 => format!("st{} [r{}+{:#x}], {:#x}", size, dst, off, imm),
+        0x03 => format!("stx{} [r{}+{:#x}], r{}", size, dst, off, src),
+        0x04 | 0x07 => {
+            let bits = if class == 0x07 { 64 } else { 32 };
+            let op = match opc & 0xf0 {
+                0x00 => "add",
+                0x10 => "sub",
+                0x20 => "mul",
+                0x30 => "div",
+                0x40 => "or",
+                0x50 => "and",
+                0x60 => "lsh",
+                0x70 => "rsh",
+                0x80 => "neg",
+                0x90 => "mod",
+                0xa0 => "xor",
+                0xb0 => "mov",
+                0xc0 => "arsh",
+                0xd0 if opc & 0x08 == 0 => return (format!("le{} r{}", imm, dst), 8),
+                0xd0 => return (format!("be{} r{}", imm, dst), 8),
+                _ => "unknown",
+            };
+            if op == "neg" {
+                format!("neg{} r{}", bits, dst)
+            } else if opc & 0x08 == 0 {
+                format!("{}{} r{}, {:#x}", op, bits, dst, imm)
+            } else {
+                format!("{}{} r{}, r{}", op, bits, dst, src)
+            }
+        }
+        0x05 => {
+            let op = match opc & 0xf0 {
+                0x00 => return (format!("ja {:+#x}", off), 8),
+                0x80 if opc & 0x08 == 0 => return (format!("call {:#x}", imm), 8),
+                0x80 => return (format!("callx {:#x}", imm), 8),
+                0x90 => return ("exit".into(), 8),
+                0x10 => "jeq",
+                0x20 => "jgt",
+                0x30 => "jge",
+                0x40 => "jset",
+                0x50 => "jne",
+                0x60 => "jsgt",
+                0x70 => "jsge",
+                0xa0 => "jlt",
+                0xb0 => "jle",
+                0xc0 => "jslt",
+                0xd0 => "jsle",
+                _ => "unknown",
+            };
+            if opc & 0x08 == 0 {
+                format!("{} r{}, {:#x}, {:+}", op, dst, imm, off)
+            } else {
+                format!("{} r{}, r{}, {:+}", op, dst, src, off)
+            }
+        }
+        _ => "unknown".into(),
+    };
+    (text, 8)
 }
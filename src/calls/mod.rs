@@ -1,154 +1,232 @@
 //! bpf-profile calls command implementation.
 
+use crate::config::{Address, Config, Cost, ProgramCounter};
 use crate::error::{Error, Result};
+use crate::trace::for_each_section;
 use crate::{filebuf, global};
+use serde_json::{json, Value};
+use std::io::{self, Write};
 use std::path::Path;
 
 /// Reads the trace input file and prints functions in order of calls.
-pub fn run(trace_path: &Path, dump_path: Option<&Path>, tab: usize) -> Result<()> {
-    if !crate::trace::contains_standard_header(filebuf::open(trace_path)?)? {
-        return Err(Error::TraceFormat);
+/// Each traced invocation is printed separately.
+/// The input is read once, so it can be the standard input.
+pub fn run(
+    trace_path: &Path,
+    dump_path: Option<&Path>,
+    config: &Config,
+    tab: usize,
+    format: &str,
+    lenient: bool,
+) -> Result<()> {
+    let mut resv = crate::resolver::read(dump_path, config)?;
+
+    let mut invocations = Vec::new();
+    for_each_section(filebuf::open_input(trace_path)?, |section| {
+        let number = section.number();
+        invocations.push((number, trace_calls(section, &mut resv, lenient)?));
+        Ok(())
+    })?;
+
+    match format {
+        "text" => print_calls(&invocations, &resv, tab),
+        "json" => write_json(io::stdout(), &invocations, &resv),
+        _ => Err(Error::Format(format.into())),
     }
+}
 
-    let max_depth;
-    let mut resv = crate::resolver::read(dump_path)?;
+/// Represents a traced call of a function (or of a syscall).
+#[derive(Debug)]
+pub(crate) struct Call {
+    depth: usize,
+    address: Address,
+    caller_pc: ProgramCounter,
+    first_line: usize,
+    last_line: usize,
+    self_cost: Cost,
+    inclusive_cost: Cost,
+}
 
-    {
-        let reader = filebuf::open(trace_path)?;
-        max_depth = update_resolver(reader, &mut resv)?;
+impl Call {
+    /// Creates new call at the line of the call instruction.
+    fn new(depth: usize, address: Address, caller_pc: ProgramCounter, lc: usize) -> Self {
+        Call {
+            depth,
+            address,
+            caller_pc,
+            first_line: lc,
+            last_line: lc,
+            self_cost: 0,
+            inclusive_cost: 0,
+        }
     }
+}
 
+/// Represents the calls of a traced invocation in order of calls
+/// and the number of all its executed instructions.
+pub(crate) type Invocation = (Vec<Call>, Cost);
+
+/// Prints functions of invocations indented according to depths of calls.
+fn print_calls(invocations: &[(usize, Invocation)], resv: &Resolver, tab: usize) -> Result<()> {
+    let max_depth = invocations
+        .iter()
+        .flat_map(|(_, (calls, _))| calls.iter().map(|c| c.depth + 1))
+        .max()
+        .unwrap_or(0);
     let depth_width = max_depth.to_string().len();
-    let reader = filebuf::open(trace_path)?;
-    trace_calls(reader, &resv, depth_width, tab)?;
+    for (number, (calls, _)) in invocations {
+        println!("Invocation {}:", number);
+        for c in calls {
+            let name = resv.resolve_by_address(c.address, c.first_line)?;
+            print_call(c.depth, &name, depth_width, tab);
+        }
+    }
+
+    Ok(())
+}
 
+/// Writes invocations as JSON: an array of trees of calls.
+/// Every call has the name and the address of the function, the pc of the call,
+/// the range of lines of the trace and numbers of executed instructions.
+pub(crate) fn write_json(
+    mut output: impl Write,
+    invocations: &[(usize, Invocation)],
+    resv: &Resolver,
+) -> Result<()> {
+    let mut document = Vec::with_capacity(invocations.len());
+    for (number, (calls, instructions)) in invocations {
+        let mut i = 0;
+        document.push(json!({
+            "invocation": number,
+            "instructions": instructions,
+            "calls": json_calls(calls, 0, &mut i, resv)?,
+        }));
+    }
+
+    serde_json::to_writer(&mut output, &document).map_err(io::Error::from)?;
+    writeln!(output)?;
+    output.flush()?;
     Ok(())
 }
 
-use crate::bpf::Instruction;
+/// Returns the calls at the depth starting from the i-th one, with their callees.
+fn json_calls(calls: &[Call], depth: usize, i: &mut usize, resv: &Resolver) -> Result<Vec<Value>> {
+    let mut result = Vec::new();
+    while let Some(c) = calls.get(*i).filter(|c| c.depth == depth) {
+        *i += 1;
+        result.push(json!({
+            "name": resv.resolve_by_address(c.address, c.first_line)?,
+            "address": c.address,
+            "caller_pc": c.caller_pc,
+            "lines": [c.first_line, c.last_line],
+            "self_instructions": c.self_cost,
+            "inclusive_instructions": c.inclusive_cost,
+            "calls": json_calls(calls, depth + 1, i, resv)?,
+        }));
+    }
+    Ok(result)
+}
+
 use crate::resolver::Resolver;
+use crate::trace::{Step, Walker};
 use std::io::BufRead;
 
-/// Parses the trace file line by line updating the resolver.
-/// Returns maximal depth of enclosed function calls.
-fn update_resolver(mut reader: impl BufRead, resv: &mut Resolver) -> Result<usize> {
+/// Parses a section of the trace line by line updating the resolver.
+/// Returns calls in order of calls and the number of executed instructions.
+/// Skips calls of ignored functions with everything they call,
+/// their instructions are counted as instructions of the caller.
+pub(crate) fn trace_calls(
+    reader: impl BufRead,
+    resv: &mut Resolver,
+    lenient: bool,
+) -> Result<Invocation> {
     if global::verbose() {
-        tracing::info!("First pass of trace: updating resolver...")
+        tracing::info!("Parsing trace, collecting calls...")
     }
 
-    let mut line = String::with_capacity(512);
-    let mut bytes_read = usize::MAX;
-    let mut ix: Instruction;
-    let mut lc = 0_usize;
-    let mut depth = 0_usize;
-    let mut max_depth = 0_usize;
-
-    while bytes_read != 0 {
-        if line.is_empty() {
-            bytes_read = filebuf::read_line(&mut reader, &mut line)?;
-            lc += 1;
-        }
-
-        let ixr = Instruction::parse(&line);
-        if let Err(Error::TraceSkipped) = &ixr {
-            /* warn!("Skip '{}'", &line.trim()); */
-            line.clear();
-            continue;
-        }
-        ix = ixr?;
-
-        if !ix.is_call() {
-            if ix.is_exit() {
-                depth -= 1;
+    let mut ignored_depth = 0_usize;
+    let mut calls = Vec::<Call>::new();
+    let mut open = Vec::<usize>::new(); // indices of calls being executed
+    let mut instructions: Cost = 0;
+
+    let config = resv.config().clone();
+    let mut walker = Walker::new(reader, config.clone(), lenient);
+    for step in &mut walker {
+        match step? {
+            Step::Instruction { .. } => count_instruction(&mut calls, &open, &mut instructions),
+            Step::Call {
+                caller_pc,
+                address,
+                first,
+                lc,
+            } => {
+                let name = resv.update(address, first.pc());
+                if ignored_depth > 0 || config.is_ignored(&name) {
+                    ignored_depth += 1;
+                } else {
+                    open.push(calls.len());
+                    calls.push(Call::new(open.len() - 1, address, caller_pc, lc));
+                }
+            }
+            Step::Syscall {
+                caller_pc,
+                address,
+                name,
+                lc,
+            } => {
+                let name = resv.update_syscall(address, &name);
+                if ignored_depth == 0 && !config.is_ignored(&name) {
+                    calls.push(Call::new(open.len(), address, caller_pc, lc));
+                }
+            }
+            Step::Exit { lc } => {
+                if ignored_depth > 0 {
+                    ignored_depth -= 1;
+                } else {
+                    // The final exit of the entrypoint has no matching call
+                    finish_call(&mut calls, &mut open, lc);
+                }
             }
-            line.clear();
-            continue;
-        }
-
-        // Handle sequences of enclosed calls as well:
-        // 604: call 0xcb3fc071
-        // 588: call 0x8e0001f9
-        // 1024: call 0x8bf38212
-        // ...
-        while ix.is_call() {
-            let address = ix.extract_call_target(lc)?;
-            depth += 1;
-            max_depth = std::cmp::max(depth, max_depth);
-            // Read next line — the first instruction of the call
-            bytes_read = filebuf::read_line(&mut reader, &mut line)?;
-            lc += 1;
-            ix = Instruction::parse(&line)?;
-            resv.update(address, ix.pc());
         }
-        // Keep here the last non-call line to process further
     }
 
-    Ok(max_depth)
+    // Exits may be missing in truncated traces
+    while !open.is_empty() {
+        finish_call(&mut calls, &mut open, walker.lines());
+    }
+    Ok((calls, instructions))
 }
 
-/// Parses the trace file line by line printing calls.
-fn trace_calls(
-    mut reader: impl BufRead,
-    resv: &Resolver,
-    depth_width: usize,
-    tab: usize,
-) -> Result<()> {
-    if global::verbose() {
-        tracing::info!("Second pass of trace: dumping functions...")
+/// Counts an executed instruction as an instruction of the innermost call.
+fn count_instruction(calls: &mut [Call], open: &[usize], instructions: &mut Cost) {
+    *instructions += 1;
+    if let Some(i) = open.last() {
+        calls[*i].self_cost += 1;
     }
+}
 
-    let mut line = String::with_capacity(512);
-    let mut bytes_read = usize::MAX;
-    let mut ix: Instruction;
-    let mut lc = 0_usize;
-    let mut depth = 0_usize;
-
-    while bytes_read != 0 {
-        if line.is_empty() {
-            bytes_read = filebuf::read_line(&mut reader, &mut line)?;
-            lc += 1;
-        }
-
-        let ixr = Instruction::parse(&line);
-        if let Err(Error::TraceSkipped) = &ixr {
-            /* warn!("Skip '{}'", &line.trim()); */
-            line.clear();
-            continue;
-        }
-        ix = ixr?;
-
-        if !ix.is_call() {
-            if ix.is_exit() {
-                depth -= 1;
-            }
-            line.clear();
-            continue;
-        }
-
-        // Handle sequences of enclosed calls as well:
-        // 604: call 0xcb3fc071
-        // 588: call 0x8e0001f9
-        // 1024: call 0x8bf38212
-        // ...
-        while ix.is_call() {
-            let address = ix.extract_call_target(lc)?;
-            let name = resv.resolve_by_address(address);
-            println!(
-                "[{:width$}] {:indent$}{}",
-                depth,
-                String::default(),
-                &name,
-                width = depth_width,
-                indent = depth * tab
-            );
-            depth += 1;
-            // Read next line — the first instruction of the call
-            bytes_read = filebuf::read_line(&mut reader, &mut line)?;
-            lc += 1;
-            ix = Instruction::parse(&line)?;
-        }
-        // Keep here the last non-call line to process further
+/// Closes the innermost call at the line of its exit,
+/// adding its inclusive cost to the caller.
+fn finish_call(calls: &mut [Call], open: &mut Vec<usize>, lc: usize) {
+    let i = match open.pop() {
+        Some(i) => i,
+        None => return,
+    };
+    calls[i].last_line = lc;
+    calls[i].inclusive_cost += calls[i].self_cost;
+    if let Some(caller) = open.last() {
+        calls[*caller].inclusive_cost += calls[i].inclusive_cost;
     }
+}
 
-    Ok(())
+/// Prints the called function indented according to the depth of the call.
+fn print_call(depth: usize, name: &str, depth_width: usize, tab: usize) {
+    println!(
+        "[{:width$}] {:indent$}{}",
+        depth,
+        String::default(),
+        name,
+        width = depth_width,
+        indent = depth * tab
+    );
 }
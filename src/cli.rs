@@ -1,24 +1,38 @@
 //! bpf-profile command line interface definition.
 
-use crate::config;
-use std::path::PathBuf;
+use bpf_profile::commands::{
+    self, CALLS_FORMATS, DEFAULT_CALLS_FORMAT, DEFAULT_FORMAT, DEFAULT_TAB, DEFAULT_TOP, FORMATS,
+};
+use bpf_profile::{Config, Error, Result};
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 
 #[derive(StructOpt)]
 #[structopt(about = "BPF trace to profile converter")]
 pub struct Application {
     #[structopt(
-         parse(from_os_str),
-         short,
-         long,
-         default_value = &config::DEFAULT_CONFIG,
-         help = "Path to the config file"
-     )]
-    pub config: PathBuf,
+        parse(from_os_str),
+        short,
+        long,
+        help = "Optional path to the config file [default: bpf-profile.conf]"
+    )]
+    pub config: Option<PathBuf>,
 
     #[structopt(short, long, help = "Shows more information")]
     pub verbose: bool,
 
+    #[structopt(
+        long,
+        help = "Logs problems of malformed input and skips them instead of stopping"
+    )]
+    pub lenient: bool,
+
+    #[structopt(
+        long,
+        help = "Removes hashes and generic parameters from names of functions"
+    )]
+    pub simplify_names: bool,
+
     #[structopt(subcommand)]
     pub cmd: Command,
 }
@@ -27,7 +41,10 @@ pub struct Application {
 pub enum Command {
     #[structopt(about = "Prints functions in order of calls")]
     Calls {
-        #[structopt(parse(from_os_str), help = "Path to the input trace file")]
+        #[structopt(
+            parse(from_os_str),
+            help = "Path to the input trace file (`-` for standard input)"
+        )]
         trace: PathBuf,
 
         #[structopt(
@@ -38,13 +55,143 @@ pub enum Command {
         )]
         dump: Option<PathBuf>,
 
-        #[structopt(short, long, default_value = "2", help = "Indentation size")]
-        tab: usize,
+        #[structopt(
+            parse(from_os_str),
+            long,
+            conflicts_with = "dump",
+            help = "Optional path to the input ELF file (enables resolving names of functions)"
+        )]
+        elf: Option<PathBuf>,
+
+        #[structopt(short, long, help = "Indentation size [default: 2]")]
+        tab: Option<usize>,
+
+        #[structopt(
+            short,
+            long,
+            possible_values(&CALLS_FORMATS),
+            help = "Optional output format: indented text or a JSON tree of calls [default: text]"
+        )]
+        format: Option<String>,
+    },
+
+    #[structopt(about = "Prints usage of stack frames by functions")]
+    Stack {
+        #[structopt(
+            parse(from_os_str),
+            help = "Path to the input trace file (`-` for standard input)"
+        )]
+        trace: PathBuf,
+
+        #[structopt(
+            parse(from_os_str),
+            short,
+            long,
+            help = "Optional path to the input dump file (enables resolving names of functions)"
+        )]
+        dump: Option<PathBuf>,
+
+        #[structopt(
+            parse(from_os_str),
+            long,
+            conflicts_with = "dump",
+            help = "Optional path to the input ELF file (enables resolving names of functions)"
+        )]
+        elf: Option<PathBuf>,
+    },
+
+    #[structopt(about = "Prints bytes read and written by functions per memory region")]
+    Memory {
+        #[structopt(
+            parse(from_os_str),
+            help = "Path to the input trace file (`-` for standard input)"
+        )]
+        trace: PathBuf,
+
+        #[structopt(
+            parse(from_os_str),
+            short,
+            long,
+            help = "Optional path to the input dump file (enables resolving names of functions)"
+        )]
+        dump: Option<PathBuf>,
+
+        #[structopt(
+            parse(from_os_str),
+            long,
+            conflicts_with = "dump",
+            help = "Optional path to the input ELF file (enables resolving names of functions)"
+        )]
+        elf: Option<PathBuf>,
+    },
+
+    #[structopt(about = "Prints basic blocks and functions executed in traces")]
+    Coverage {
+        #[structopt(
+            parse(from_os_str),
+            required = true,
+            help = "Paths to the input trace files (`-` for standard input)"
+        )]
+        traces: Vec<PathBuf>,
+
+        #[structopt(
+            parse(from_os_str),
+            short,
+            long,
+            help = "Path to the input dump file (required unless the ELF file is given)"
+        )]
+        dump: Option<PathBuf>,
+
+        #[structopt(
+            parse(from_os_str),
+            long,
+            conflicts_with = "dump",
+            help = "Path to the input ELF file (required unless the dump file is given)"
+        )]
+        elf: Option<PathBuf>,
+
+        #[structopt(
+            short,
+            long,
+            help = "Lists blocks which have not been executed in executed functions"
+        )]
+        uncovered: bool,
+    },
+
+    #[structopt(about = "Prints functions sorted by their own costs")]
+    Top {
+        #[structopt(
+            parse(from_os_str),
+            help = "Path to the input trace file (`-` for standard input)"
+        )]
+        trace: PathBuf,
+
+        #[structopt(
+            parse(from_os_str),
+            short,
+            long,
+            help = "Optional path to the input dump file (enables resolving names of functions)"
+        )]
+        dump: Option<PathBuf>,
+
+        #[structopt(
+            parse(from_os_str),
+            long,
+            conflicts_with = "dump",
+            help = "Optional path to the input ELF file (enables resolving names of functions)"
+        )]
+        elf: Option<PathBuf>,
+
+        #[structopt(short, long, help = "Number of printed functions [default: 30]")]
+        number: Option<usize>,
     },
 
     #[structopt(about = "Generates performance profile")]
     Generate {
-        #[structopt(parse(from_os_str), help = "Path to the input trace file")]
+        #[structopt(
+            parse(from_os_str),
+            help = "Path to the input trace file (`-` for standard input)"
+        )]
         trace: PathBuf,
 
         #[structopt(
@@ -63,14 +210,21 @@ pub enum Command {
         )]
         dump: Option<PathBuf>,
 
+        #[structopt(
+            parse(from_os_str),
+            long,
+            conflicts_with = "dump",
+            help = "Optional path to the input ELF file (enables resolving names of functions)"
+        )]
+        elf: Option<PathBuf>,
+
         #[structopt(
             short,
             long,
-            possible_values(&config::FORMATS),
-            default_value = &config::DEFAULT_FORMAT,
-            help = "Optional format of the generated profile"
+            possible_values(&FORMATS),
+            help = "Optional format of the generated profile [default: callgrind]"
         )]
-        format: String,
+        format: Option<String>,
 
         #[structopt(
             parse(from_os_str),
@@ -79,6 +233,39 @@ pub enum Command {
             help = "Optional path to the generated profile [default: standard output]"
         )]
         output: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            help = "Generates one profile per traced invocation into numbered output files"
+        )]
+        split: bool,
+    },
+
+    #[structopt(about = "Compares profiles of two traces")]
+    Diff {
+        #[structopt(parse(from_os_str), help = "Path to the old input trace file")]
+        old: PathBuf,
+
+        #[structopt(parse(from_os_str), help = "Path to the new input trace file")]
+        new: PathBuf,
+
+        #[structopt(
+            parse(from_os_str),
+            short,
+            long,
+            number_of_values = 1,
+            help = "Optional paths to the input dump (or ELF) files of the old and the new trace \
+                    (a single file is used for both)"
+        )]
+        dump: Vec<PathBuf>,
+
+        #[structopt(
+            parse(from_os_str),
+            short,
+            long,
+            help = "Optional path to the generated callgrind profile of delta costs"
+        )]
+        output: Option<PathBuf>,
     },
 }
 
@@ -86,3 +273,131 @@ pub enum Command {
 pub fn application() -> Application {
     Application::from_args()
 }
+
+/// Dispatches CLI commands.
+/// Options missing in the command line are taken from the config file.
+pub fn execute(app: Application) -> Result<()> {
+    bpf_profile::set_verbose(app.verbose);
+    let mut cfg = Config::read(app.config.as_deref())?;
+    cfg.simplify_names |= app.simplify_names;
+    let cfg = &cfg;
+
+    match app.cmd {
+        Command::Calls {
+            trace,
+            dump,
+            elf,
+            tab,
+            format,
+        } => {
+            commands::calls(
+                &trace,
+                symbols(elf.as_deref().or(dump.as_deref()), cfg),
+                cfg,
+                tab.or(cfg.tab).unwrap_or(DEFAULT_TAB),
+                format.as_deref().unwrap_or(DEFAULT_CALLS_FORMAT),
+                app.lenient,
+            )?;
+        }
+
+        Command::Stack { trace, dump, elf } => {
+            commands::stack(
+                &trace,
+                symbols(elf.as_deref().or(dump.as_deref()), cfg),
+                cfg,
+                app.lenient,
+            )?;
+        }
+
+        Command::Memory { trace, dump, elf } => {
+            commands::memory(
+                &trace,
+                symbols(elf.as_deref().or(dump.as_deref()), cfg),
+                cfg,
+                app.lenient,
+            )?;
+        }
+
+        Command::Coverage {
+            traces,
+            dump,
+            elf,
+            uncovered,
+        } => {
+            commands::coverage(
+                &traces,
+                symbols(elf.as_deref().or(dump.as_deref()), cfg),
+                cfg,
+                uncovered,
+                app.lenient,
+            )?;
+        }
+
+        Command::Top {
+            trace,
+            dump,
+            elf,
+            number,
+        } => {
+            commands::top(
+                &trace,
+                symbols(elf.as_deref().or(dump.as_deref()), cfg),
+                cfg,
+                number.unwrap_or(DEFAULT_TOP),
+                app.lenient,
+            )?;
+        }
+
+        Command::Generate {
+            trace,
+            asm,
+            dump,
+            elf,
+            format,
+            output,
+            split,
+        } => {
+            commands::generate(
+                &trace,
+                asm.as_deref().or(cfg.asm.as_deref()),
+                symbols(elf.as_deref().or(dump.as_deref()), cfg),
+                cfg,
+                format
+                    .as_deref()
+                    .or(cfg.format.as_deref())
+                    .unwrap_or(DEFAULT_FORMAT),
+                output.as_ref().map(|p| p.as_ref()), // Option<T> -> Option<&T>
+                split,
+                app.lenient,
+            )?;
+        }
+
+        Command::Diff {
+            old,
+            new,
+            dump,
+            output,
+        } => {
+            if dump.len() > 2 {
+                return Err(Error::DiffDumps(dump.len()));
+            }
+            commands::diff(
+                &old,
+                &new,
+                symbols(dump.first().map(|p| p.as_ref()), cfg),
+                symbols(dump.last().map(|p| p.as_ref()), cfg),
+                cfg,
+                output.as_deref(),
+                app.lenient,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Chooses the source of function names: the command line options,
+/// then the ELF file or the dump file from the config file.
+fn symbols<'a>(path: Option<&'a Path>, cfg: &'a Config) -> Option<&'a Path> {
+    path.or(cfg.elf.as_deref()).or(cfg.dump.as_deref())
+}
@@ -1,26 +1,197 @@
 //! bpf-profile config module.
 
-pub const FAILURE: i32 = 1;
+use crate::error::{Error, Result};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
+/// Name of the config file looked up in the current directory.
 pub const DEFAULT_CONFIG: &str = "bpf-profile.conf";
 
-pub const FORMATS: &[&str] = &["callgrind"];
+/// Supported output formats of profiles.
+pub const FORMATS: &[&str] = &[
+    "callgrind",
+    "pprof",
+    "folded",
+    "speedscope",
+    "chrome",
+    "lcov",
+];
+/// Output format used if none is given.
 pub const DEFAULT_FORMAT: &str = "callgrind";
 
+/// Supported output formats of the `calls` command.
+pub const CALLS_FORMATS: &[&str] = &["text", "json"];
+/// Output format of the `calls` command used if none is given.
+pub const DEFAULT_CALLS_FORMAT: &str = "text";
+
+/// Indentation of nested calls in the `calls` output.
+pub const DEFAULT_TAB: usize = 2;
+
+/// Number of functions printed by the `top` command.
+pub const DEFAULT_TOP: usize = 30;
+
+/// File name of the assembly listing used if none is given.
 pub const DEFAULT_ASM: &str = "<none>";
 
+/// Separator of columns in the assembly listing.
 pub const PADDING: &str = "        ";
 
+/// Compute units of an instruction if the config file does not set its class.
+pub const DEFAULT_INSTRUCTION_UNITS: Cost = 1;
+/// Compute units of a syscall unknown to the builtin list and to the config file.
+pub const DEFAULT_SYSCALL_UNITS: Cost = 100;
+
+/// Cost of execution: number of instructions or compute units.
 pub type Cost = usize;
+/// Index into a vector.
 pub type Index = usize;
+/// Address of a function: the target of a call.
 pub type Address = usize;
+/// Program counter: number of an instruction from the start of the program.
 pub type ProgramCounter = usize;
 
+/// Address of the root of the call tree.
 pub const GROUND_ZERO: Address = Address::MAX;
 
+/// Map used for registries of functions and calls.
 #[cfg(not(test))]
 pub type Map<K, V> = std::collections::HashMap<K, V>;
 
-// Use less performant BTree in tests for deterministic sequences
+/// Map used for registries of functions and calls.
+/// Use less performant BTree in tests for deterministic sequences.
 #[cfg(test)]
 pub type Map<K, V> = std::collections::BTreeMap<K, V>;
+
+/// Represents settings from the config file.
+/// Command line options override the corresponding settings.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Path to the input dump file.
+    pub dump: Option<PathBuf>,
+    /// Path to the input ELF file (takes precedence over the dump file).
+    pub elf: Option<PathBuf>,
+    /// Path to the generated assembly file.
+    pub asm: Option<PathBuf>,
+    /// Format of the generated profile.
+    pub format: Option<String>,
+    /// Indentation size of the calls command.
+    pub tab: Option<usize>,
+    /// Removes hashes and generic parameters from names of functions.
+    pub simplify_names: bool,
+    /// Names to replace names of functions.
+    aliases: BTreeMap<String, String>,
+    /// Regular expressions of names of functions which should be ignored.
+    ignore: Vec<String>,
+    #[serde(skip)]
+    ignore_regexes: Vec<Regex>,
+    /// Names of syscalls in addition to the built-in ones.
+    syscalls: Vec<String>,
+    #[serde(skip)]
+    syscall_names: BTreeMap<u32, String>,
+    /// Costs in compute units which override the default ones.
+    compute_units: ComputeUnits,
+}
+
+/// Represents the table of costs in compute units.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ComputeUnits {
+    /// Costs of instructions by classes of their opcodes.
+    classes: BTreeMap<String, Cost>,
+    /// Costs of syscalls by their names.
+    syscalls: BTreeMap<String, Cost>,
+}
+
+impl Config {
+    /// Reads the config file (if any).
+    /// Missing default config file means default settings.
+    pub fn read(filepath: Option<&Path>) -> Result<Self> {
+        let filepath = match filepath {
+            Some(path) => path,
+            None if Path::new(DEFAULT_CONFIG).exists() => Path::new(DEFAULT_CONFIG),
+            None => return Ok(Config::default()),
+        };
+        let text =
+            std::fs::read_to_string(filepath).map_err(|e| Error::OpenFile(e, filepath.into()))?;
+        let mut config = Config::parse(&text).map_err(|e| Error::Config(e, filepath.into()))?;
+        // Relative paths of the config file are relative to its directory
+        if let Some(dir) = filepath.parent() {
+            for path in [&mut config.dump, &mut config.elf, &mut config.asm]
+                .into_iter()
+                .flatten()
+            {
+                *path = dir.join(&path);
+            }
+        }
+        Ok(config)
+    }
+
+    /// Parses the text of config file.
+    fn parse(text: &str) -> std::result::Result<Self, String> {
+        let mut config: Config = toml::from_str(text).map_err(|e| e.to_string())?;
+        if let Some(format) = &config.format {
+            if !FORMATS.contains(&format.as_str()) {
+                return Err(format!("unsupported format '{}'", format));
+            }
+        }
+        for class in config.compute_units.classes.keys() {
+            if !crate::bpf::CLASSES.contains(&class.as_str()) {
+                return Err(format!("unknown instruction class '{}'", class));
+            }
+        }
+        for pattern in &config.ignore {
+            let re = Regex::new(pattern).map_err(|e| e.to_string())?;
+            config.ignore_regexes.push(re);
+        }
+        for name in &config.syscalls {
+            let hash = crate::bpf::hash_symbol_name(name.as_bytes());
+            config.syscall_names.insert(hash, name.clone());
+        }
+        Ok(config)
+    }
+
+    /// Returns the alias of a function name (if any) or the name itself.
+    pub fn alias(&self, name: &str) -> String {
+        self.aliases
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| name.to_string())
+    }
+
+    /// Returns name of the additional syscall with the hash (if any).
+    pub fn syscall(&self, hash: u32) -> Option<String> {
+        self.syscall_names.get(&hash).cloned()
+    }
+
+    /// Returns cost in compute units of an instruction of the opcode class.
+    pub fn instruction_units(&self, class: &str) -> Cost {
+        self.compute_units
+            .classes
+            .get(class)
+            .copied()
+            .unwrap_or(DEFAULT_INSTRUCTION_UNITS)
+    }
+
+    /// Returns cost in compute units of a call of the syscall.
+    pub fn syscall_units(&self, name: &str) -> Cost {
+        self.compute_units
+            .syscalls
+            .get(name)
+            .copied()
+            .or_else(|| {
+                crate::bpf::SYSCALLS
+                    .iter()
+                    .find(|(syscall, _)| *syscall == name)
+                    .map(|(_, units)| *units)
+            })
+            .unwrap_or(DEFAULT_SYSCALL_UNITS)
+    }
+
+    /// Checks if a function should be ignored.
+    pub fn is_ignored(&self, name: &str) -> bool {
+        self.ignore_regexes.iter().any(|re| re.is_match(name))
+    }
+}
@@ -0,0 +1,187 @@
+//! bpf-profile coverage command implementation.
+
+use crate::config::{Config, ProgramCounter};
+use crate::error::{Error, Result};
+use crate::resolver::Resolver;
+use crate::trace::{for_each_section, Step, Walker};
+use crate::{filebuf, global};
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// Reads the traces and prints executed basic blocks and functions of the program.
+pub fn run(
+    trace_paths: &[PathBuf],
+    dump_path: Option<&Path>,
+    config: &Config,
+    uncovered: bool,
+    lenient: bool,
+) -> Result<()> {
+    let resv = crate::resolver::read(dump_path, config)?;
+    if resv.is_default() {
+        return Err(Error::CoverageWithoutDump);
+    }
+
+    let mut coverage = Coverage::default();
+    for trace_path in trace_paths {
+        for_each_section(filebuf::open_input(trace_path)?, |section| {
+            coverage.trace(section, &resv, lenient)
+        })?;
+    }
+
+    coverage.write(&resv, io::stdout(), uncovered)
+}
+
+/// Represents a basic block: a range of instructions which starts
+/// at the first instruction of a function or at a label of the dump.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    /// Label of the block (empty for the first block of a function).
+    pub label: String,
+    /// Program counter of the first instruction.
+    pub first_pc: ProgramCounter,
+    /// Whether any instruction of the block has been executed.
+    pub executed: bool,
+}
+
+/// Represents coverage of a function by its basic blocks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FunctionCoverage {
+    /// Name of the function.
+    pub name: String,
+    /// Basic blocks of the function in order of their pcs.
+    pub blocks: Vec<Block>,
+}
+
+impl FunctionCoverage {
+    /// Returns number of executed blocks.
+    pub fn executed(&self) -> usize {
+        self.blocks.iter().filter(|b| b.executed).count()
+    }
+}
+
+/// Represents instructions executed in a set of traces.
+#[derive(Debug, Default)]
+pub struct Coverage {
+    executed: BTreeSet<ProgramCounter>,
+}
+
+impl Coverage {
+    /// Parses a section of the trace line by line, marking executed instructions.
+    pub fn trace(&mut self, reader: impl BufRead, resv: &Resolver, lenient: bool) -> Result<()> {
+        if global::verbose() {
+            tracing::info!("Parsing trace, collecting executed instructions...")
+        }
+
+        for step in Walker::new(reader, resv.config().clone(), lenient) {
+            if let Step::Instruction { ix, .. } = step? {
+                self.executed.insert(ix.pc());
+            }
+        }
+        Ok(())
+    }
+
+    /// Splits functions of the program into basic blocks by labels of the dump
+    /// and checks which of them have been executed.
+    pub fn functions(&self, resv: &Resolver) -> Vec<FunctionCoverage> {
+        let functions = resv.functions();
+        let labels = resv.labels();
+        let mut result = Vec::with_capacity(functions.len());
+        for (i, (first_pc, name)) in functions.iter().enumerate() {
+            let end_pc = functions
+                .get(i + 1)
+                .map(|(pc, _)| *pc)
+                .unwrap_or_else(|| resv.end_pc());
+            let mut starts = vec![(*first_pc, String::default())];
+            starts.extend(
+                labels
+                    .range(first_pc + 1..end_pc)
+                    .map(|(pc, label)| (*pc, label.clone())),
+            );
+            let mut blocks = Vec::with_capacity(starts.len());
+            for (j, (pc, label)) in starts.iter().enumerate() {
+                let end = starts.get(j + 1).map(|(pc, _)| *pc).unwrap_or(end_pc);
+                blocks.push(Block {
+                    label: label.clone(),
+                    first_pc: *pc,
+                    executed: self.executed.range(pc..&end).next().is_some(),
+                });
+            }
+            result.push(FunctionCoverage {
+                name: name.to_string(),
+                blocks,
+            });
+        }
+        result
+    }
+
+    /// Writes totals of executed functions and blocks, then the table of functions
+    /// in order of their pcs. If `uncovered` is set, lists blocks which have not
+    /// been executed in executed functions.
+    pub fn write(&self, resv: &Resolver, mut output: impl Write, uncovered: bool) -> Result<()> {
+        let functions = self.functions(resv);
+        let executed_functions = functions.iter().filter(|f| f.executed() > 0).count();
+        let blocks = functions.iter().map(|f| f.blocks.len()).sum::<usize>();
+        let executed_blocks = functions.iter().map(|f| f.executed()).sum::<usize>();
+
+        writeln!(
+            output,
+            "Functions executed: {} of {} ({:.1}%)",
+            executed_functions,
+            functions.len(),
+            percent(executed_functions, functions.len())
+        )?;
+        writeln!(
+            output,
+            "Blocks executed: {} of {} ({:.1}%)",
+            executed_blocks,
+            blocks,
+            percent(executed_blocks, blocks)
+        )?;
+
+        writeln!(output)?;
+        writeln!(
+            output,
+            "{:>8} {:>8} {:>7}  Function",
+            "Blocks", "Executed", "Percent"
+        )?;
+        for function in &functions {
+            let executed = function.executed();
+            writeln!(
+                output,
+                "{:>8} {:>8} {:>6.1}%  {}",
+                function.blocks.len(),
+                executed,
+                percent(executed, function.blocks.len()),
+                function.name
+            )?;
+            if !uncovered || executed == 0 {
+                continue;
+            }
+            for block in function.blocks.iter().filter(|b| !b.executed) {
+                let label = if block.label.is_empty() {
+                    "start"
+                } else {
+                    &block.label
+                };
+                writeln!(
+                    output,
+                    "{:>27}  not executed: {} (pc {})",
+                    "", label, block.first_pc
+                )?;
+            }
+        }
+
+        output.flush()?;
+        Ok(())
+    }
+}
+
+/// Returns the share of a part in percents.
+fn percent(part: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / total as f64
+    }
+}
@@ -0,0 +1,190 @@
+//! bpf-profile diff command implementation.
+
+use crate::config::Config;
+use crate::error::Result;
+use crate::gen::{self, Summary, Totals};
+use crate::{filebuf, global};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Reads both trace files and prints changes of costs of functions.
+/// Writes delta costs in the callgrind format if the output path is given.
+pub fn run(
+    old_trace_path: &Path,
+    new_trace_path: &Path,
+    old_dump_path: Option<&Path>,
+    new_dump_path: Option<&Path>,
+    config: &Config,
+    output_path: Option<&Path>,
+    lenient: bool,
+) -> Result<()> {
+    let old = gen::summarize(old_trace_path, old_dump_path, config, lenient)?;
+    let new = gen::summarize(new_trace_path, new_dump_path, config, lenient)?;
+
+    write_table(io::stdout(), &old, &new)?;
+    if let Some(output_path) = output_path {
+        write_callgrind(filebuf::open_w(output_path)?, &old, &new)?;
+    }
+
+    Ok(())
+}
+
+/// Represents the change of costs of a function (or of calls between two functions).
+struct Delta {
+    old: Option<Totals>,
+    new: Option<Totals>,
+}
+
+impl Delta {
+    fn new(old: Option<&Totals>, new: Option<&Totals>) -> Self {
+        Delta {
+            old: old.copied(),
+            new: new.copied(),
+        }
+    }
+
+    fn self_cost(&self) -> i64 {
+        self.delta(|t| t.self_cost)
+    }
+
+    fn inclusive_cost(&self) -> i64 {
+        self.delta(|t| t.inclusive_cost)
+    }
+
+    fn calls(&self) -> i64 {
+        self.delta(|t| t.calls)
+    }
+
+    /// Checks if the function appeared, disappeared, or its costs changed.
+    fn is_changed(&self) -> bool {
+        self.old != self.new
+    }
+
+    fn status(&self) -> &'static str {
+        match (&self.old, &self.new) {
+            (None, Some(_)) => " (added)",
+            (Some(_), None) => " (removed)",
+            _ => "",
+        }
+    }
+
+    fn delta(&self, value: impl Fn(&Totals) -> usize) -> i64 {
+        let old = self.old.as_ref().map_or(0, &value) as i64;
+        let new = self.new.as_ref().map_or(0, &value) as i64;
+        new - old
+    }
+}
+
+/// Writes the table of changed functions sorted by the change of their inclusive costs.
+pub fn write_table(mut output: impl Write, old: &Summary, new: &Summary) -> Result<()> {
+    if global::verbose() {
+        tracing::info!("Comparing profiles...")
+    }
+
+    let mut deltas = union(&old.functions, &new.functions)
+        .into_iter()
+        .map(|f| (f, Delta::new(old.functions.get(f), new.functions.get(f))))
+        .filter(|(_, d)| d.is_changed())
+        .collect::<Vec<_>>();
+    deltas.sort_by(|(f1, d1), (f2, d2)| {
+        let key = |d: &Delta| (d.inclusive_cost().abs(), d.self_cost().abs());
+        key(d2).cmp(&key(d1)).then(f1.cmp(f2))
+    });
+
+    writeln!(
+        output,
+        "Total instructions: {} -> {} ({:+})",
+        old.total_cost,
+        new.total_cost,
+        new.total_cost as i64 - old.total_cost as i64
+    )?;
+    writeln!(
+        output,
+        "{:>10} {:>10} {:>8}  Function",
+        "Self", "Inclusive", "Calls"
+    )?;
+    for (f, d) in &deltas {
+        writeln!(
+            output,
+            "{:>+10} {:>+10} {:>+8}  {}{}",
+            d.self_cost(),
+            d.inclusive_cost(),
+            d.calls(),
+            f,
+            d.status()
+        )?;
+    }
+
+    output.flush()?;
+    Ok(())
+}
+
+/// Writes delta costs in the callgrind format.
+/// Costs can not be negative, so increases and decreases are separate events.
+/// Functions and calls with unchanged costs are left out.
+pub fn write_callgrind(mut output: impl Write, old: &Summary, new: &Summary) -> Result<()> {
+    if global::verbose() {
+        tracing::info!("Writing callgrind profile of delta costs...")
+    }
+
+    let split = |delta: i64| (delta.max(0), (-delta).max(0));
+    let functions = union(&old.functions, &new.functions);
+    let mut calls = BTreeMap::<&String, Vec<&(String, String)>>::new();
+    for key in union(&old.calls, &new.calls) {
+        if Delta::new(old.calls.get(key), new.calls.get(key)).is_changed() {
+            calls.entry(&key.0).or_default().push(key);
+        }
+    }
+
+    let mut totals = (0, 0);
+    for f in &functions {
+        let (increase, decrease) =
+            split(Delta::new(old.functions.get(*f), new.functions.get(*f)).self_cost());
+        totals.0 += increase;
+        totals.1 += decrease;
+    }
+
+    writeln!(output, "# callgrind format")?;
+    writeln!(output, "version: 1")?;
+    writeln!(output, "creator: bpf-profile")?;
+    writeln!(output, "positions: line")?;
+    writeln!(output, "events: Increase Decrease")?;
+    writeln!(output, "totals: {} {}", totals.0, totals.1)?;
+
+    for f in functions {
+        let delta = Delta::new(old.functions.get(f), new.functions.get(f));
+        let (increase, decrease) = split(delta.self_cost());
+        if increase == decrease && !calls.contains_key(f) {
+            continue;
+        }
+        writeln!(output, "\nfn={}", f)?;
+        if increase != decrease {
+            writeln!(output, "0 {} {}", increase, decrease)?;
+        }
+
+        for key in calls.get(f).into_iter().flatten() {
+            let (old_calls, new_calls) = (old.calls.get(*key), new.calls.get(*key));
+            let delta = Delta::new(old_calls, new_calls);
+            let (increase, decrease) = split(delta.inclusive_cost());
+            let number_of_calls = std::cmp::max(
+                old_calls.map_or(0, |t| t.calls),
+                new_calls.map_or(0, |t| t.calls),
+            );
+            writeln!(output, "cfn={}", key.1)?;
+            writeln!(output, "calls={} 0", number_of_calls)?;
+            writeln!(output, "0 {} {}", increase, decrease)?;
+        }
+    }
+
+    output.flush()?;
+    Ok(())
+}
+
+/// Returns sorted keys of both maps without repetitions.
+fn union<'a, K: Ord>(
+    old: &'a BTreeMap<K, Totals>,
+    new: &'a BTreeMap<K, Totals>,
+) -> BTreeSet<&'a K> {
+    old.keys().chain(new.keys()).collect()
+}
@@ -6,32 +6,104 @@ use std::path::PathBuf;
 /// Represents errors of the converter.
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
+    /// The file path is not valid UTF-8.
     #[error("Unsupported file name '{0}'")]
     Filename(PathBuf),
 
+    /// A file cannot be opened (or read as a whole).
     #[error("Cannot open file '{1}': {0}")]
     OpenFile(#[source] io::Error, PathBuf),
+    /// A line of input cannot be read.
     #[error("Cannot read line '{1}': {0}")]
     ReadLine(#[source] io::Error, String),
+    /// Reading or writing failed.
     #[error("Input/output error: {0}")]
     Io(#[from] io::Error),
 
+    /// The config file is malformed.
+    #[error("Cannot parse config file '{1}': {0}")]
+    Config(String, PathBuf),
+
+    /// The dump file has no ELF header.
     #[error("Unsupported format of dump file: should contain standard header")]
     DumpFormat,
+    /// The dump file has no disassembly section.
     #[error("Dump file without disassembly")]
     DumpFormatNoDisasm,
+    /// A line of the dump file cannot be parsed.
     #[error("Cannot parse instruction '{0}' of a function at line '{1}'")]
     DumpParsing(String, usize),
+    /// The ELF file is malformed.
+    #[error("Cannot parse ELF file: {0}")]
+    ElfParsing(String),
+    /// The ELF file has no code.
+    #[error("ELF file without .text section")]
+    ElfNoText,
+    /// Debug info of the ELF file is malformed.
+    #[error("Cannot parse debug info of ELF file: {0}")]
+    DwarfParsing(String),
 
+    /// The trace has no traced invocations.
     #[error("Unsupported format of trace file: should contain standard header")]
     TraceFormat,
+    /// The line of the trace is not an instruction (used internally to skip it).
     #[error("Skipped input")]
     TraceSkipped,
+    /// An instruction expected to be a call is not a call.
     #[error("Instruction at line {1} is not a call: '{0}'")]
     TraceNotCall(String, usize),
+    /// An instruction of the trace cannot be parsed.
     #[error("Cannot parse trace instruction '{0}' at line {1}")]
     TraceParsing(String, usize),
+    /// A call is not followed by the first instruction of the callee.
+    #[error("Call '{0}' at line {1} is not followed by an instruction of the callee")]
+    TraceNoCallee(String, usize),
+    /// An exit has no matching call.
+    #[error("Exit at line {0} without call")]
+    TraceExitWithoutCall(usize),
+    /// The same pc has different instructions in the trace.
+    #[error("Inconsistent input at line {2}: expected '{0}', got '{1}'")]
+    TraceInconsistent(String, String, usize),
+    /// Too many lines of other threads are kept aside while reading a section.
+    #[error("More than {0} lines of other threads are pending, the log is interleaved too much")]
+    TracePending(usize),
+    /// The instruction counter skips: lines of several sections are mixed up.
+    #[error("Instruction counter skips at line {1}: '{0}', traces are interleaved in the log without threads")]
+    TraceInterleaved(String, usize),
+    /// A called address is unknown to the resolver.
+    #[error("Unknown function at address 0x{0:x} called at line {1}")]
+    UnknownAddress(usize, usize),
+
+    /// The output format is not supported.
+    #[error("Unsupported format of profile '{0}'")]
+    Format(String),
+    /// Splitting into invocations is requested without the output file.
+    #[error("Splitting the trace into invocations requires the output file")]
+    SplitWithoutOutput,
+    /// The lcov tracefile has no source file to refer to.
+    #[error("Lcov requires the assembly listing (--asm) or the line table of the ELF file")]
+    LcovWithoutSource,
+    /// More than two dump files are given to compare traces.
+    #[error("Expected at most two dump files (of the old and the new trace), got {0}")]
+    DiffDumps(usize),
+    /// Coverage is requested without the dump file or the ELF file.
+    #[error("Coverage requires the dump file or the ELF file of the program")]
+    CoverageWithoutDump,
 }
 
 /// Represents results.
 pub type Result<T> = std::result::Result<T, Error>;
+
+/// Returns the value of a successful result.
+/// In the lenient mode logs the error and returns None, so that the caller
+/// can skip the problem and go on; otherwise returns the error.
+pub(crate) fn recover<T>(result: Result<T>, lenient: bool) -> Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(err) if lenient => {
+            tracing::warn!("{}", err);
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
+}
@@ -1,16 +1,64 @@
 //! bpf-profile file buffered utilities module.
 
 use crate::error::{Error, Result};
+use flate2::read::MultiGzDecoder;
 use std::fs::{self, File, OpenOptions};
-use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
 use std::path::Path;
 
+/// Path of the input which means the standard input.
+pub const STDIN: &str = "-";
+
+const GZIP_MAGIC: &[u8] = b"\x1f\x8b";
+const ZSTD_MAGIC: &[u8] = b"\x28\xb5\x2f\xfd";
+
 /// Opens a file for buffered reading.
 pub fn open(filepath: &Path) -> Result<impl BufRead> {
-    let file = File::open(&filepath).map_err(|e| Error::OpenFile(e, filepath.into()))?;
+    let file = File::open(filepath).map_err(|e| Error::OpenFile(e, filepath.into()))?;
     Ok(BufReader::new(file))
 }
 
+/// Opens an input file (or the standard input for `-`) for buffered reading.
+/// Decompresses gzip and zstd input transparently, recognizing it by the magic bytes.
+pub fn open_input(filepath: &Path) -> Result<Box<dyn BufRead>> {
+    open_input_with(filepath, io::stdin())
+}
+
+/// Opens an input file for buffered reading, `stdin` for `-`.
+pub(crate) fn open_input_with(
+    filepath: &Path,
+    stdin: impl Read + 'static,
+) -> Result<Box<dyn BufRead>> {
+    let input: Box<dyn Read> = if filepath == Path::new(STDIN) {
+        Box::new(stdin)
+    } else {
+        Box::new(File::open(filepath).map_err(|e| Error::OpenFile(e, filepath.into()))?)
+    };
+    decompress(input).map_err(|e| Error::OpenFile(e, filepath.into()))
+}
+
+/// Reads the magic bytes of the input (all of them, as reads may return less)
+/// and wraps the input into the decoder of its compression format if any.
+fn decompress(mut input: Box<dyn Read>) -> io::Result<Box<dyn BufRead>> {
+    let mut magic = Vec::with_capacity(ZSTD_MAGIC.len());
+    input
+        .by_ref()
+        .take(ZSTD_MAGIC.len() as u64)
+        .read_to_end(&mut magic)?;
+    let is_gzip = magic.starts_with(GZIP_MAGIC);
+    let is_zstd = magic.starts_with(ZSTD_MAGIC);
+    let reader = BufReader::new(io::Cursor::new(magic).chain(input));
+    if is_gzip {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    } else if is_zstd {
+        Ok(Box::new(BufReader::new(zstd::Decoder::with_buffer(
+            reader,
+        )?)))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
 /// Opens a file for buffered writing; rewrites existing.
 pub fn open_w(filepath: &Path) -> Result<impl Write> {
     if filepath.exists() {
@@ -19,7 +67,8 @@ pub fn open_w(filepath: &Path) -> Result<impl Write> {
     let file = OpenOptions::new()
         .write(true)
         .create(true)
-        .open(&filepath)
+        .truncate(true)
+        .open(filepath)
         .map_err(|e| Error::OpenFile(e, filepath.into()))?;
     Ok(BufWriter::new(file))
 }
@@ -1,53 +1,64 @@
 //! bpf-profile-generate asm module.
 
-use crate::bpf::Instruction;
+use crate::bpf::{Instruction, MAX_INSTRUCTIONS};
+use crate::config::ProgramCounter;
 use std::path::{Path, PathBuf};
 
 /// Represents generated assembly file.
 #[derive(Debug)]
 pub struct Source {
     output_path: PathBuf,
+    end_pc: ProgramCounter,
     ixs: Vec<Instruction>,
 }
 
-use crate::error::Result;
+use crate::error::{recover, Error, Result};
 use crate::resolver::Resolver;
 use crate::{filebuf, global};
 use std::io::Write;
 
 impl Source {
-    /// Creates new instance of Source.
-    pub fn new(output_path: &Path) -> Self {
+    /// Creates new instance of Source for the program which ends before `end_pc`
+    /// (or of the maximum size if the program is unknown).
+    pub fn new(output_path: &Path, end_pc: Option<ProgramCounter>) -> Self {
         Source {
             output_path: output_path.into(),
+            end_pc: end_pc.unwrap_or(MAX_INSTRUCTIONS),
             ixs: Vec::new(),
         }
     }
 
     /// Adds new instruction to the listing.
-    pub fn add_instruction(&mut self, ix: &Instruction) {
+    /// Returns an error if the pc is out of the program
+    /// or the trace has different instructions at the same pc.
+    pub fn add_instruction(&mut self, ix: &Instruction, lc: usize) -> Result<()> {
+        if ix.pc() == 0 || ix.pc() >= self.end_pc {
+            return Err(Error::TraceParsing(ix.to_string(), lc));
+        }
         let index = ix.pc() - 1;
         if index >= self.ixs.len() {
             self.ixs.resize(index + 1, Instruction::default());
         }
         if self.ixs[index].is_empty() {
-            self.ixs[index] = ix.clone();
-        } else if self.ixs[index] != *ix {
-            panic!(
-                "Inconsistent input: expected '{}', got '{}'",
-                &self.ixs[index], &ix
-            );
+            self.ixs[index] = Instruction::new(ix.pc(), ix.text());
+        } else if !self.ixs[index].has_same_code(ix) {
+            return Err(Error::TraceInconsistent(
+                self.ixs[index].to_string(),
+                ix.to_string(),
+                lc,
+            ));
         }
+        Ok(())
     }
 
     /// Writes all lines of the listing to a file.
-    pub fn write(&self, resv: &Resolver) -> Result<()> {
+    pub fn write(&self, resv: &Resolver, lenient: bool) -> Result<()> {
         if global::verbose() {
             tracing::info!("Writing assembly file...")
         }
         let output = filebuf::open_w(&self.output_path)?;
         if resv.is_default() {
-            write_assembly_from_trace(output, &self.ixs, resv)?;
+            write_assembly_from_trace(output, &self.ixs, resv, lenient)?;
         } else {
             resv.write_pretty_source(output)?;
         }
@@ -63,6 +74,7 @@ fn write_assembly_from_trace(
     mut output: impl Write,
     ixs: &[Instruction],
     resv: &Resolver,
+    lenient: bool,
 ) -> Result<()> {
     for (i, ix) in ixs.iter().enumerate() {
         if ix.is_empty() {
@@ -79,14 +91,24 @@ fn write_assembly_from_trace(
             Some(name) => format!("{}; {}", PADDING, &name),
         };
 
-        if !ix.is_call() {
-            writeln!(output, "{}{}", ix, comment)?;
+        let target = if !ix.is_call() || ix.is_indirect_call() {
+            // Targets of indirect calls vary, keep the register operand
+            None
         } else {
-            let op = ix.extract_call_operation(i)?;
-            let address = ix.extract_call_target(i)?;
-            let name = resv.resolve_by_address(address);
-            let ix = Instruction::new(ix.pc(), format!("{} {}", &op, &name));
-            writeln!(output, "{}{}", ix, comment)?;
+            recover(
+                ix.extract_call_operation(i).and_then(|op| {
+                    let address = ix.extract_call_target(i)?;
+                    Ok((op, resv.resolve_by_address(address, i)?))
+                }),
+                lenient,
+            )?
+        };
+        match target {
+            None => writeln!(output, "{}{}", ix, comment)?,
+            Some((op, name)) => {
+                let ix = Instruction::new(ix.pc(), format!("{} {}", &op, &name));
+                writeln!(output, "{}{}", ix, comment)?;
+            }
         }
     }
 
@@ -0,0 +1,57 @@
+//! bpf-profile-generate chrome module.
+//! Implements writing of the profile in the Chrome Trace Event format,
+//! readable by Perfetto and `chrome://tracing`.
+
+use super::events::{Events, Kind};
+use super::profile::Functions;
+use crate::error::Result;
+use crate::global;
+use serde_json::json;
+use std::io::Write;
+
+const PID: usize = 1;
+
+/// Writes the profile data as duration events of function calls.
+/// Every top-level call (usually an invocation) gets a separate thread,
+/// the number of executed instructions serves as the timestamp.
+pub fn write(mut output: impl Write, functions: &Functions, events: &Events) -> Result<()> {
+    if global::verbose() {
+        tracing::info!("Writing Chrome trace events...")
+    }
+
+    let mut trace_events = Vec::new();
+    for (i, call) in events.top_level_calls().into_iter().enumerate() {
+        let tid = i + 1;
+        let first = call.first().expect("Empty call");
+        trace_events.push(json!({
+            "name": "thread_name",
+            "ph": "M",
+            "pid": PID,
+            "tid": tid,
+            "args": { "name": functions[&first.address].name() },
+        }));
+        for e in call {
+            let ph = match e.kind {
+                Kind::Open => "B",
+                Kind::Close => "E",
+            };
+            trace_events.push(json!({
+                "name": functions[&e.address].name(),
+                "ph": ph,
+                "ts": e.instructions,
+                "pid": PID,
+                "tid": tid,
+            }));
+        }
+    }
+
+    let document = json!({
+        "traceEvents": trace_events,
+        "otherData": { "creator": "bpf-profile", "clock": "instructions" },
+    });
+    serde_json::to_writer(&mut output, &document).map_err(std::io::Error::from)?;
+    writeln!(output)?;
+
+    output.flush()?;
+    Ok(())
+}
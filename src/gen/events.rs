@@ -0,0 +1,79 @@
+//! bpf-profile-generate events module.
+//! Implements the timeline of calls in order of execution.
+
+use crate::config::{Address, Cost};
+
+/// Represents kind of an event.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Open,
+    Close,
+}
+
+/// Represents opening or closing of a call at a moment of the timeline.
+/// The moment is given by two clocks: the line of the trace section
+/// (of the call or the exit instruction) and the number of instructions
+/// executed before the event.
+#[derive(Clone, Debug)]
+pub struct Event {
+    pub kind: Kind,
+    pub address: Address,
+    pub line: usize,
+    pub instructions: Cost,
+}
+
+/// Represents the timeline of calls.
+#[derive(Debug, Default)]
+pub struct Events {
+    events: Vec<Event>,
+    stack: Vec<Address>,
+}
+
+impl Events {
+    /// Opens a call at the moment.
+    pub fn open(&mut self, address: Address, line: usize, instructions: Cost) {
+        self.stack.push(address);
+        self.events.push(Event {
+            kind: Kind::Open,
+            address,
+            line,
+            instructions,
+        });
+    }
+
+    /// Closes the last opened call at the moment.
+    pub fn close(&mut self, line: usize, instructions: Cost) {
+        if let Some(address) = self.stack.pop() {
+            self.events.push(Event {
+                kind: Kind::Close,
+                address,
+                line,
+                instructions,
+            });
+        }
+    }
+
+    /// Splits the timeline into sequences of events, one per top-level call.
+    pub fn top_level_calls(&self) -> Vec<&[Event]> {
+        let mut calls = Vec::new();
+        let mut depth = 0_usize;
+        let mut begin = 0_usize;
+        for (i, e) in self.events.iter().enumerate() {
+            match e.kind {
+                Kind::Open => {
+                    if depth == 0 {
+                        begin = i;
+                    }
+                    depth += 1;
+                }
+                Kind::Close => {
+                    depth -= 1;
+                    if depth == 0 {
+                        calls.push(&self.events[begin..=i]);
+                    }
+                }
+            }
+        }
+        calls
+    }
+}
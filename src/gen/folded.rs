@@ -0,0 +1,67 @@
+//! bpf-profile-generate folded module.
+//! Implements writing of the profile as collapsed stacks for flame graphs.
+
+use super::profile::Functions;
+use super::stacks::Stacks;
+use crate::config::{Cost, GROUND_ZERO};
+use crate::error::Result;
+use crate::global;
+use crate::resolver::Resolver;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// Writes the profile data as folded stacks: one line per distinct stack
+/// of function names from the outermost to the innermost, with its cost.
+/// Every stack starts with the program's entrypoint, named as in summaries:
+/// invocations become the entrypoint, stacks of the root are nested under it.
+/// See details of the format in the FlameGraph documentation.
+pub fn write(
+    mut output: impl Write,
+    functions: &Functions,
+    stacks: &Stacks,
+    resolver: &Resolver,
+) -> Result<()> {
+    if global::verbose() {
+        tracing::info!("Writing folded stacks...")
+    }
+
+    let entrypoint = functions[&GROUND_ZERO].entrypoint_name(resolver);
+
+    // Collapse stacks which differ only in pcs, sort them by names
+    let mut folded = BTreeMap::<String, Cost>::new();
+    for (frames, cost) in stacks.samples() {
+        let mut names = Vec::with_capacity(frames.len() + 1);
+        let outermost = frames.last().map(|(address, _)| *address);
+        let in_invocation = outermost
+            .filter(|address| *address != GROUND_ZERO)
+            .is_some_and(|address| functions[&address].is_invocation());
+        if !in_invocation {
+            names.push(frame_name(&entrypoint));
+        }
+        for (address, _) in frames.iter().rev() {
+            let f = match *address {
+                GROUND_ZERO => continue,
+                _ => &functions[address],
+            };
+            let name = match f.is_invocation() {
+                true => f.entrypoint_name(resolver),
+                false => f.name(),
+            };
+            names.push(frame_name(&name));
+        }
+        *folded.entry(names.join(";")).or_insert(0) += cost;
+    }
+
+    for (stack, cost) in &folded {
+        writeln!(output, "{} {}", stack, cost)?;
+    }
+
+    output.flush()?;
+    Ok(())
+}
+
+/// Replaces separators of frames in a function name, e.g. in `<[u8; 32] as Trait>`.
+/// Spaces are kept: tools split the count off at the last space of the line.
+pub fn frame_name(name: &str) -> String {
+    name.replace(';', ",")
+}
@@ -0,0 +1,90 @@
+//! bpf-profile-generate lcov module.
+//! Implements writing of the coverage in the lcov tracefile format (as `geninfo` does).
+//! See details of the format in the `geninfo` manual page.
+
+use super::profile::Functions;
+use crate::config::{Cost, ProgramCounter};
+use crate::error::Result;
+use crate::global;
+use crate::resolver::Resolver;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// Represents coverage of one source file: functions and execution counts of lines.
+#[derive(Default)]
+struct Record {
+    functions: Vec<(usize, String, Cost)>,
+    lines: BTreeMap<usize, Cost>,
+}
+
+/// Writes execution counts of lines of the assembly listing (or of the Rust sources,
+/// if the line table is available) as an lcov tracefile.
+/// The count of a line is the count of its most executed instruction.
+/// Instructions of the dump (or the ELF) file which have not been executed
+/// are written with zero counts.
+pub fn write(
+    mut output: impl Write,
+    functions: &Functions,
+    resolver: &Resolver,
+    source_filename: &str,
+) -> Result<()> {
+    if global::verbose() {
+        tracing::info!("Writing lcov tracefile...")
+    }
+
+    let mut executed = BTreeMap::<ProgramCounter, Cost>::new();
+    for f in functions.values() {
+        for (pc, count) in f.executed() {
+            *executed.entry(pc).or_default() += count;
+        }
+    }
+    for pc in resolver.instructions() {
+        executed.entry(pc).or_default();
+    }
+
+    let position = |pc: ProgramCounter| resolver.source_line(pc).unwrap_or((source_filename, pc));
+    let mut records = BTreeMap::<&str, Record>::new();
+    for (pc, count) in &executed {
+        let (file, line) = position(*pc);
+        let hits = records
+            .entry(file)
+            .or_default()
+            .lines
+            .entry(line)
+            .or_default();
+        *hits = std::cmp::max(*hits, *count);
+    }
+    for (first_pc, name) in resolver.functions() {
+        let (file, line) = position(first_pc);
+        let count = executed.get(&first_pc).copied().unwrap_or_default();
+        records
+            .entry(file)
+            .or_default()
+            .functions
+            .push((line, name.to_string(), count));
+    }
+
+    writeln!(output, "TN:")?;
+    for (file, record) in &records {
+        writeln!(output, "SF:{}", file)?;
+        for (line, name, _) in &record.functions {
+            writeln!(output, "FN:{},{}", line, name)?;
+        }
+        for (_, name, count) in &record.functions {
+            writeln!(output, "FNDA:{},{}", count, name)?;
+        }
+        writeln!(output, "FNF:{}", record.functions.len())?;
+        let hit = record.functions.iter().filter(|(.., c)| *c > 0).count();
+        writeln!(output, "FNH:{}", hit)?;
+        for (line, count) in &record.lines {
+            writeln!(output, "DA:{},{}", line, count)?;
+        }
+        writeln!(output, "LF:{}", record.lines.len())?;
+        let hit = record.lines.values().filter(|c| **c > 0).count();
+        writeln!(output, "LH:{}", hit)?;
+        writeln!(output, "end_of_record")?;
+    }
+
+    output.flush()?;
+    Ok(())
+}
@@ -1,33 +1,106 @@
 //! bpf-profile generate command implementation.
 
 mod asm;
+mod chrome;
+mod events;
+mod folded;
+mod lcov;
+mod pprof;
 mod profile;
+mod speedscope;
+mod stacks;
+mod summary;
 mod trace;
 
 #[cfg(test)]
 mod tests;
 
-use crate::config::DEFAULT_ASM;
+use crate::config::{Config, DEFAULT_ASM};
 use crate::error::{Error, Result};
 use crate::filebuf;
-use std::io;
-use std::path::Path;
-use trace::Profile;
+use crate::trace::for_each_section;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+pub use summary::{Summary, Totals};
+pub use trace::Profile;
 
 /// Runs the conversion from BPF trace to a profiler output.
+#[allow(clippy::too_many_arguments)]
 pub fn run(
     trace_path: &Path,
     asm_path: Option<&Path>,
     dump_path: Option<&Path>,
-    _: &str, // always 'callgrind' currently
+    config: &Config,
+    format: &str,
     output_path: Option<&Path>,
+    split: bool,
+    lenient: bool,
 ) -> Result<()> {
-    if !crate::trace::contains_standard_header(filebuf::open(trace_path)?)? {
-        return Err(Error::TraceFormat);
+    if split {
+        let output_path = output_path.ok_or(Error::SplitWithoutOutput)?;
+        return run_split(
+            trace_path,
+            asm_path,
+            dump_path,
+            config,
+            format,
+            output_path,
+            lenient,
+        );
     }
 
-    let profile = Profile::create(trace_path, dump_path, asm_path)?;
+    let profile = Profile::create(trace_path, dump_path, asm_path, config, lenient)?;
+    write(&profile, format, asm_path, output_path)
+}
+
+/// Reads the trace and returns the summary of costs by names of functions.
+pub fn summarize(
+    trace_path: &Path,
+    dump_path: Option<&Path>,
+    config: &Config,
+    lenient: bool,
+) -> Result<Summary> {
+    let profile = Profile::create(trace_path, dump_path, None, config, lenient)?;
+    Ok(profile.summary())
+}
 
+/// Generates a separate profile for every traced invocation.
+/// Output files are numbered in order of invocations.
+fn run_split(
+    trace_path: &Path,
+    asm_path: Option<&Path>,
+    dump_path: Option<&Path>,
+    config: &Config,
+    format: &str,
+    output_path: &Path,
+    lenient: bool,
+) -> Result<()> {
+    let resv = crate::resolver::read(dump_path, config)?;
+    for_each_section(filebuf::open_input(trace_path)?, |section| {
+        let number = section.number();
+        let asm_path = asm_path.map(|p| numbered(p, number));
+        let mut profile = Profile::new(resv.clone(), asm_path.as_deref())?;
+        profile.set_lenient(lenient);
+        profile.enter_invocation(number);
+        trace::parse(section, &mut profile)?;
+        profile.leave_invocation();
+        write(
+            &profile,
+            format,
+            asm_path.as_deref(),
+            Some(&numbered(output_path, number)),
+        )
+    })
+}
+
+/// Writes the profile in the format to a file or to the standard output.
+fn write(
+    profile: &Profile,
+    format: &str,
+    asm_path: Option<&Path>,
+    output_path: Option<&Path>,
+) -> Result<()> {
     let source_filename = match asm_path {
         None => DEFAULT_ASM,
         Some(asm_path) => asm_path
@@ -35,11 +108,21 @@ pub fn run(
             .ok_or_else(|| Error::Filename(asm_path.into()))?,
     };
 
-    match output_path {
-        None => profile.write_callgrind(io::stdout(), source_filename),
-        Some(output_path) => {
-            let output = filebuf::open_w(output_path)?;
-            profile.write_callgrind(output, source_filename)
-        }
+    // Fail before the output file is created
+    if format == "lcov" && !profile.has_source() {
+        return Err(Error::LcovWithoutSource);
     }
+    let output: Box<dyn Write> = match output_path {
+        None => Box::new(io::stdout()),
+        Some(output_path) => Box::new(filebuf::open_w(output_path)?),
+    };
+
+    profile.write(format, output, source_filename)
+}
+
+/// Appends ordinal number of an invocation to the file path.
+fn numbered(path: &Path, number: usize) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(format!(".{}", number));
+    path.into()
 }
@@ -0,0 +1,163 @@
+//! bpf-profile-generate pprof module.
+//! Implements writing of the profile in the pprof format: gzipped `profile.proto`.
+//! See details of the format in the `perftools.profiles` protobuf definition.
+
+use super::profile::Functions;
+use super::stacks::{Frame, Stacks};
+use crate::config::{Address, Map};
+use crate::error::Result;
+use crate::global;
+use flate2::{write::GzEncoder, Compression};
+use std::io::Write;
+
+/// Writes the profile data in the pprof format.
+/// Samples are weighted by number of instructions.
+pub fn write(
+    output: impl Write,
+    functions: &Functions,
+    stacks: &Stacks,
+    source_filename: &str,
+) -> Result<()> {
+    if global::verbose() {
+        tracing::info!("Writing pprof profile...")
+    }
+
+    let mut strings = StringTable::default();
+    let mut profile = Message::default();
+
+    let mut value_type = Message::default();
+    value_type.varint(1, strings.index("instructions"));
+    value_type.varint(2, strings.index("count"));
+    profile.message(1, &value_type); // sample_type
+    profile.message(11, &value_type); // period_type
+    profile.varint(12, 1); // period
+
+    let filename = strings.index(source_filename);
+    let mut function_ids = Map::<Address, u64>::new();
+    let mut location_ids = Map::<Frame, u64>::new();
+
+    for (frames, cost) in stacks.samples() {
+        let mut ids = Vec::with_capacity(frames.len());
+        for frame in frames {
+            let (address, pc) = frame;
+            let next_function_id = function_ids.len() as u64 + 1;
+            let function_id = *function_ids.entry(address).or_insert_with(|| {
+                let name = strings.index(&functions[&address].name());
+                let mut function = Message::default();
+                function.varint(1, next_function_id);
+                function.varint(2, name); // name
+                function.varint(3, name); // system_name
+                function.varint(4, filename);
+                profile.message(5, &function);
+                next_function_id
+            });
+            let next_location_id = location_ids.len() as u64 + 1;
+            let location_id = *location_ids.entry(frame).or_insert_with(|| {
+                let mut line = Message::default();
+                line.varint(1, function_id);
+                line.varint(2, pc as u64);
+                let mut location = Message::default();
+                location.varint(1, next_location_id);
+                location.varint(3, pc as u64); // address
+                location.message(4, &line);
+                profile.message(4, &location);
+                next_location_id
+            });
+            ids.push(location_id);
+        }
+        let mut sample = Message::default();
+        sample.packed(1, &ids); // location_id
+        sample.packed(2, &[cost as u64]); // value
+        profile.message(2, &sample);
+    }
+
+    for s in &strings.strings {
+        profile.bytes(6, s.as_bytes()); // string_table
+    }
+
+    let mut output = GzEncoder::new(output, Compression::default());
+    output.write_all(&profile.buf)?;
+    output.finish()?.flush()?;
+    Ok(())
+}
+
+/// Represents the table of strings referenced by their indices.
+struct StringTable {
+    strings: Vec<String>,
+    indices: Map<String, u64>,
+}
+
+impl Default for StringTable {
+    fn default() -> Self {
+        // The first string must be empty
+        let mut table = StringTable {
+            strings: Vec::new(),
+            indices: Map::new(),
+        };
+        table.index("");
+        table
+    }
+}
+
+impl StringTable {
+    /// Returns index of the string, adding it to the table if needed.
+    fn index(&mut self, s: &str) -> u64 {
+        if let Some(i) = self.indices.get(s) {
+            return *i;
+        }
+        let i = self.strings.len() as u64;
+        self.strings.push(s.into());
+        self.indices.insert(s.into(), i);
+        i
+    }
+}
+
+/// Represents an encoded protobuf message.
+#[derive(Default)]
+struct Message {
+    buf: Vec<u8>,
+}
+
+const WIRE_VARINT: u64 = 0;
+const WIRE_LEN: u64 = 2;
+
+impl Message {
+    /// Encodes an integer field.
+    fn varint(&mut self, field: u64, value: u64) {
+        self.key(field, WIRE_VARINT);
+        self.raw_varint(value);
+    }
+
+    /// Encodes a string or bytes field.
+    fn bytes(&mut self, field: u64, value: &[u8]) {
+        self.key(field, WIRE_LEN);
+        self.raw_varint(value.len() as u64);
+        self.buf.extend_from_slice(value);
+    }
+
+    /// Encodes an embedded message field.
+    fn message(&mut self, field: u64, value: &Message) {
+        self.bytes(field, &value.buf);
+    }
+
+    /// Encodes a packed repeated integer field.
+    fn packed(&mut self, field: u64, values: &[u64]) {
+        let mut packed = Message::default();
+        for v in values {
+            packed.raw_varint(*v);
+        }
+        self.bytes(field, &packed.buf);
+    }
+
+    fn key(&mut self, field: u64, wire_type: u64) {
+        self.raw_varint(field << 3 | wire_type);
+    }
+
+    fn raw_varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buf.push((value as u8 & 0x7f) | 0x80);
+            value >>= 7;
+        }
+        self.buf.push(value as u8);
+    }
+}
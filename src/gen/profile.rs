@@ -1,10 +1,54 @@
 //! bpf-profile-generate profile module.
 
+use crate::bpf::{Instruction, CATEGORIES, ENTRYPOINT};
 use crate::config::{Address, Cost, Map, ProgramCounter};
 use std::collections::BTreeMap;
 
 pub type Functions = Map<Address, Function>;
-type Costs = BTreeMap<ProgramCounter, Cost>; // sort by pc
+type Costs = BTreeMap<ProgramCounter, Counters>; // sort by pc
+
+/// Number of events: instructions, compute units and categories of instructions.
+const NUMBER_OF_EVENTS: usize = 2 + CATEGORIES.len();
+
+/// Represents costs of execution: one counter per event.
+pub type Counters = [Cost; NUMBER_OF_EVENTS];
+
+/// Returns names of events in order of counters.
+pub fn events() -> String {
+    format!("Instructions ComputeUnits {}", CATEGORIES.join(" "))
+}
+
+/// Returns counters of one executed instruction which consumes `units` compute units.
+pub fn instruction_counters(ix: &Instruction, units: Cost) -> Counters {
+    let mut counters = Counters::default();
+    counters[0] = 1;
+    counters[1] = units;
+    if let Some(i) = CATEGORIES.iter().position(|c| *c == ix.category()) {
+        counters[2 + i] = 1;
+    }
+    counters
+}
+
+/// Returns counters of a syscall (without traced instructions)
+/// which consumes `units` compute units.
+pub fn syscall_counters(units: Cost) -> Counters {
+    let mut counters = Counters::default();
+    counters[1] = units;
+    counters
+}
+
+/// Adds counters to the sum.
+pub fn add_counters(sum: &mut Counters, counters: &Counters) {
+    for (s, c) in sum.iter_mut().zip(counters) {
+        *s += c;
+    }
+}
+
+/// Formats counters as a line of costs.
+pub fn format_counters(counters: &Counters) -> String {
+    let costs: Vec<String> = counters.iter().map(|c| c.to_string()).collect();
+    costs.join(" ")
+}
 
 /// Represents a function which will be dumped into a profile.
 #[derive(Debug)]
@@ -13,11 +57,17 @@ pub struct Function {
     name: String,
     costs: Costs,
     calls: Vec<Call>,
+    invocation: bool,
 }
 
 use crate::config::GROUND_ZERO;
 use crate::resolver::Resolver;
 
+/// Returns pseudo address of a traced invocation, which never clashes with real ones.
+pub fn invocation_address(number: usize) -> Address {
+    GROUND_ZERO - number
+}
+
 impl Function {
     /// Creates initial function object which stores total cost of entire program.
     pub fn ground_zero() -> Self {
@@ -26,6 +76,18 @@ impl Function {
             name: "GROUND_ZERO".into(),
             costs: BTreeMap::new(),
             calls: Vec::new(),
+            invocation: false,
+        }
+    }
+
+    /// Creates function object which represents a traced invocation of the program.
+    pub fn invocation(address: Address, number: usize) -> Self {
+        Function {
+            address,
+            name: format!("invocation_{}", number),
+            costs: BTreeMap::new(),
+            calls: Vec::new(),
+            invocation: true,
         }
     }
 
@@ -44,6 +106,7 @@ impl Function {
             name,
             costs: BTreeMap::new(),
             calls: Vec::new(),
+            invocation: false,
         }
     }
 
@@ -52,11 +115,30 @@ impl Function {
         self.name.clone()
     }
 
+    /// Checks if the function represents a traced invocation of the program.
+    pub fn is_invocation(&self) -> bool {
+        self.invocation
+    }
+
+    /// Returns name of the program's entrypoint executed by the root or by an invocation:
+    /// the function which starts at the first executed pc (if resolved).
+    pub fn entrypoint_name(&self, resolver: &Resolver) -> String {
+        self.costs
+            .keys()
+            .next()
+            .and_then(|pc| resolver.resolve_by_first_pc(*pc))
+            .unwrap_or_else(|| ENTRYPOINT.into())
+    }
+
+    /// Returns numbers of executed instructions by their pcs.
+    pub fn executed(&self) -> impl Iterator<Item = (ProgramCounter, Cost)> + '_ {
+        self.costs.iter().map(|(pc, counters)| (*pc, counters[0]))
+    }
+
     /// Increments the immediate cost of the function.
-    pub fn increment_cost(&mut self, pc: ProgramCounter) {
+    pub fn increment_cost(&mut self, pc: ProgramCounter, counters: &Counters) {
         tracing::debug!("Function(0x{:x}).increment_cost", self.address);
-        let c = *self.costs.entry(pc).or_insert(0);
-        self.costs.insert(pc, c + 1);
+        add_counters(self.costs.entry(pc).or_default(), counters);
     }
 
     /// Adds finished enclosed call for this function.
@@ -76,13 +158,13 @@ pub struct Call {
     address: Address,
     caller: Address,
     caller_pc: ProgramCounter,
-    cost: Cost,
+    cost: Counters,
     callee: Box<Option<Call>>,
     depth: usize,
+    recursive: bool,
 }
 
-use crate::bpf::Instruction;
-use crate::error::{Error, Result};
+use crate::error::Result;
 
 impl Call {
     /// Creates new call object.
@@ -91,22 +173,13 @@ impl Call {
             address,
             caller: Address::default(), // will be found later
             caller_pc,
-            cost: 0,
+            cost: Counters::default(),
             callee: Box::new(None),
             depth: 0,
+            recursive: false,
         }
     }
 
-    /// Creates new call object from a trace instruction (which must be a call).
-    pub fn from(ix: &Instruction, lc: usize) -> Result<Self> {
-        let text = ix.text();
-        if !ix.is_call() {
-            return Err(Error::TraceNotCall(text, lc));
-        }
-        let address = ix.extract_call_target(lc)?;
-        Ok(Call::new(address, ix.pc()))
-    }
-
     /// Returns address of the call.
     pub fn address(&self) -> Address {
         self.address
@@ -122,29 +195,40 @@ impl Call {
         self.caller
     }
 
+    /// Returns pc of the call instruction in the caller.
+    pub fn caller_pc(&self) -> ProgramCounter {
+        self.caller_pc
+    }
+
     /// Returns depth of enclosed callees.
     pub fn depth(&self) -> usize {
         self.depth
     }
 
     /// Increments the cost of this call.
-    pub fn increment_cost(&mut self, pc: ProgramCounter, functions: &mut Functions) {
+    pub fn increment_cost(
+        &mut self,
+        pc: ProgramCounter,
+        counters: &Counters,
+        functions: &mut Functions,
+    ) {
         tracing::debug!("Call(0x{:x}).increment_cost", self.address);
         match *self.callee {
             Some(ref mut callee) => {
-                callee.increment_cost(pc, functions);
+                callee.increment_cost(pc, counters, functions);
             }
             None => {
-                self.cost += 1;
+                add_counters(&mut self.cost, counters);
                 let f = functions
                     .get_mut(&self.address)
                     .expect("Call address not found in the registry of functions");
-                f.increment_cost(pc);
+                f.increment_cost(pc, counters);
             }
         }
     }
 
     /// Adds next call to the call stack.
+    /// Marks the call as recursive if the function is on the stack already.
     pub fn push_call(&mut self, mut call: Call) {
         tracing::debug!(
             "Call(0x{:x}).push_call 0x{:x} depth={}",
@@ -153,32 +237,35 @@ impl Call {
             self.depth
         );
         self.depth += 1;
+        call.recursive |= self.address == call.address;
         match *self.callee {
             Some(ref mut callee) => {
                 callee.push_call(call);
             }
             None => {
                 call.caller = self.address;
-                let old = std::mem::replace(&mut *self.callee, Some(call));
+                let old = self.callee.replace(call);
                 assert!(old.is_none());
             }
         }
     }
 
     /// Removes current call from the call stack.
-    pub fn pop_call(&mut self) -> Call {
+    /// Returns None if there are no enclosed calls.
+    pub fn pop_call(&mut self) -> Option<Call> {
         tracing::debug!("Call(0x{:x}).pop_call depth={}", self.address, self.depth);
         if self.depth == 0 {
-            panic!("Exit without call");
+            return None;
         }
         self.depth -= 1;
+        // The depth counts enclosed calls, so a call with nonzero depth has a callee
         let callee = self.callee.as_mut().as_mut().expect("Missing callee");
         if callee.callee.is_some() {
             callee.pop_call()
         } else {
             let call = self.callee.take().expect("Missing callee");
-            self.cost += call.cost;
-            call
+            add_counters(&mut self.cost, &call.cost);
+            Some(call)
         }
     }
 }
@@ -187,20 +274,28 @@ use crate::global;
 use std::io::Write;
 
 /// Writes information about calls of functions and their costs.
+/// Positions are source lines if the resolver knows them (from debug info),
+/// otherwise lines of the assembly listing `asm_fl`.
 pub fn write_callgrind_functions(
     mut output: impl Write,
     functions: &Functions,
+    resolver: &Resolver,
+    asm_fl: &str,
     line_by_line_profile_enabled: bool,
 ) -> Result<()> {
     if global::verbose() {
         tracing::info!("Writing callgrind profile...")
     }
 
+    let source_lines_enabled = resolver.has_source_lines();
+    let position = |pc: ProgramCounter| resolver.source_line(pc).unwrap_or((asm_fl, pc));
+    let source_file = |f: &Function| f.costs.keys().next().map_or(asm_fl, |pc| position(*pc).0);
+
     // Collapse possible calls of functions from different pcs
     // in case line_by_line_profile_enabled == false
     let mut addresses = Map::new();
 
-    // Collect (caller-pc, function-address) => (number-of-calls, inclusive-cost)
+    // Collect (caller-pc, function-address) => (number-of-calls, inclusive-costs)
     let mut statistics = Map::new();
 
     for (a, f) in functions {
@@ -209,42 +304,103 @@ pub fn write_callgrind_functions(
         }
 
         // Dump costs of current function
-        writeln!(output, "\nfn={}", f.name())?;
-        if line_by_line_profile_enabled {
+        let mut fi = source_file(f);
+        if source_lines_enabled {
+            writeln!(output, "\nfl={}", fi)?;
+            writeln!(output, "fn={}", f.name())?;
+            // Several instructions usually come from one source line
+            let mut lines: Vec<(&str, usize, Counters)> = Vec::new();
             for (pc, cost) in &f.costs {
-                writeln!(output, "{} {}", pc, cost)?;
+                let (file, line) = position(*pc);
+                match lines.last_mut() {
+                    Some((f, l, sum)) if *f == file && *l == line => add_counters(sum, cost),
+                    _ => lines.push((file, line, *cost)),
+                }
+            }
+            for (file, line, cost) in lines {
+                if file != fi {
+                    writeln!(output, "fi={}", file)?;
+                    fi = file;
+                }
+                writeln!(output, "{} {}", line, format_counters(&cost))?;
             }
         } else {
-            let first_pc = f.costs.iter().next().expect("Empty function").0;
-            let total_cost = f.costs.values().sum::<Cost>();
-            writeln!(output, "{} {}", first_pc, total_cost)?;
+            writeln!(output, "\nfn={}", f.name())?;
+            if line_by_line_profile_enabled {
+                for (pc, cost) in &f.costs {
+                    writeln!(output, "{} {}", pc, format_counters(cost))?;
+                }
+            } else if let Some((first_pc, _)) = f.costs.iter().next() {
+                let mut total_cost = Counters::default();
+                for cost in f.costs.values() {
+                    add_counters(&mut total_cost, cost);
+                }
+                writeln!(output, "{} {}", first_pc, format_counters(&total_cost))?;
+            }
         }
 
         // Collect statistics of callees
         addresses.clear();
         statistics.clear();
         for c in &f.calls {
-            let key = if line_by_line_profile_enabled {
+            let key = if line_by_line_profile_enabled || source_lines_enabled {
                 (c.caller_pc, c.address)
             } else {
                 let pc = addresses.entry(c.address).or_insert(c.caller_pc);
                 let unified_caller_pc = *pc;
                 (unified_caller_pc, c.address)
             };
-            let stat = statistics.entry(key).or_insert((0_usize, 0_usize));
-            let number_of_calls = stat.0 + 1;
-            let inclusive_cost = stat.1 + c.cost;
-            statistics.insert(key, (number_of_calls, inclusive_cost));
+            let (number_of_calls, inclusive_cost) = statistics
+                .entry(key)
+                .or_insert((0_usize, Counters::default()));
+            *number_of_calls += 1;
+            add_counters(inclusive_cost, &c.cost);
         }
 
         // Finally dump the statistics
         for ((pc, address), (number_of_calls, inclusive_cost)) in &statistics {
+            let mut line = *pc;
+            if source_lines_enabled {
+                let (file, source_line) = position(*pc);
+                if file != fi {
+                    writeln!(output, "fi={}", file)?;
+                    fi = file;
+                }
+                writeln!(output, "cfl={}", source_file(&functions[address]))?;
+                line = source_line;
+            }
             writeln!(output, "cfn={}", functions[address].name)?;
             writeln!(output, "calls={} 0x{:x}", number_of_calls, address)?;
-            writeln!(output, "{} {}", pc, inclusive_cost)?;
+            writeln!(output, "{} {}", line, format_counters(inclusive_cost))?;
         }
     }
 
     output.flush()?;
     Ok(())
 }
+
+use super::summary::Summary;
+
+/// Summarizes instruction costs of functions by their names.
+/// Calls from the root are counted, but the root itself is omitted.
+/// Inclusive costs of recursive calls are included in the outermost calls already.
+/// Invocations are merged into the program's entrypoint, which they execute.
+pub fn summarize(functions: &Functions, resolver: &Resolver) -> Summary {
+    let name = |f: &Function| match f.invocation {
+        true => f.entrypoint_name(resolver),
+        false => f.name(),
+    };
+    let mut summary = Summary::default();
+    for (a, f) in functions {
+        let caller = (*a != GROUND_ZERO).then(|| name(f));
+        if let Some(caller) = &caller {
+            let self_cost = f.costs.values().map(|c| c[0]).sum();
+            summary.add_self_cost(caller.clone(), self_cost);
+        }
+        for c in &f.calls {
+            let inclusive_cost = if c.recursive { 0 } else { c.cost[0] };
+            summary.add_call(caller.clone(), name(&functions[&c.address]), inclusive_cost);
+        }
+    }
+    summary
+}
@@ -0,0 +1,64 @@
+//! bpf-profile-generate speedscope module.
+//! Implements writing of the profile in the speedscope "evented" format.
+//! See details of the format in the speedscope file format schema.
+
+use super::events::{Events, Kind};
+use super::profile::Functions;
+use crate::config::{Address, Map};
+use crate::error::Result;
+use crate::global;
+use serde_json::json;
+use std::io::Write;
+
+const SCHEMA: &str = "https://www.speedscope.app/file-format-schema.json";
+
+/// Writes the profile data in the speedscope format.
+/// Every top-level call (usually an invocation) becomes a separate profile,
+/// the line of the trace section serves as the clock.
+pub fn write(mut output: impl Write, functions: &Functions, events: &Events) -> Result<()> {
+    if global::verbose() {
+        tracing::info!("Writing speedscope profile...")
+    }
+
+    let mut frames = Vec::new();
+    let mut frame_indices = Map::<Address, usize>::new();
+    let mut profiles = Vec::new();
+
+    for call in events.top_level_calls() {
+        let mut evented = Vec::with_capacity(call.len());
+        for e in call {
+            let frame = *frame_indices.entry(e.address).or_insert_with(|| {
+                frames.push(json!({ "name": functions[&e.address].name() }));
+                frames.len() - 1
+            });
+            let kind = match e.kind {
+                Kind::Open => "O",
+                Kind::Close => "C",
+            };
+            evented.push(json!({ "type": kind, "frame": frame, "at": e.line }));
+        }
+        let first = call.first().expect("Empty call");
+        let last = call.last().expect("Empty call");
+        profiles.push(json!({
+            "type": "evented",
+            "name": functions[&first.address].name(),
+            "unit": "none",
+            "startValue": first.line,
+            "endValue": last.line,
+            "events": evented,
+        }));
+    }
+
+    let document = json!({
+        "$schema": SCHEMA,
+        "shared": { "frames": frames },
+        "profiles": profiles,
+        "activeProfileIndex": 0,
+        "exporter": "bpf-profile",
+    });
+    serde_json::to_writer(&mut output, &document).map_err(std::io::Error::from)?;
+    writeln!(output)?;
+
+    output.flush()?;
+    Ok(())
+}
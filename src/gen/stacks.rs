@@ -0,0 +1,94 @@
+//! bpf-profile-generate stacks module.
+//! Implements the calling context tree which keeps costs of every distinct call stack.
+
+use crate::config::{Address, Cost, Index, Map, ProgramCounter, GROUND_ZERO};
+use std::collections::BTreeMap;
+
+/// Represents a frame of a call stack: function address and pc inside of the function.
+pub type Frame = (Address, ProgramCounter);
+
+/// Represents the calling context tree.
+#[derive(Debug)]
+pub struct Stacks {
+    nodes: Vec<Node>,
+    current: Index,
+}
+
+/// Represents a distinct call stack (a node of the tree).
+#[derive(Debug)]
+struct Node {
+    address: Address,
+    caller_pc: ProgramCounter,
+    parent: Index,
+    children: Map<(ProgramCounter, Address), Index>,
+    costs: BTreeMap<ProgramCounter, Cost>, // sort by pc
+}
+
+impl Node {
+    fn new(address: Address, caller_pc: ProgramCounter, parent: Index) -> Self {
+        Node {
+            address,
+            caller_pc,
+            parent,
+            children: Map::new(),
+            costs: BTreeMap::new(),
+        }
+    }
+}
+
+const ROOT: Index = 0;
+
+impl Default for Stacks {
+    fn default() -> Self {
+        Stacks {
+            nodes: vec![Node::new(GROUND_ZERO, 0, ROOT)],
+            current: ROOT,
+        }
+    }
+}
+
+impl Stacks {
+    /// Increments the cost of the pc in the current call stack.
+    pub fn increment_cost(&mut self, pc: ProgramCounter) {
+        *self.nodes[self.current].costs.entry(pc).or_insert(0) += 1;
+    }
+
+    /// Extends the current call stack with a callee.
+    pub fn push_call(&mut self, address: Address, caller_pc: ProgramCounter) {
+        let key = (caller_pc, address);
+        let next = self.nodes.len();
+        let child = *self.nodes[self.current].children.entry(key).or_insert(next);
+        if child == next {
+            self.nodes.push(Node::new(address, caller_pc, self.current));
+        }
+        self.current = child;
+    }
+
+    /// Returns from the current callee to its caller.
+    pub fn pop_call(&mut self) {
+        self.current = self.nodes[self.current].parent;
+    }
+
+    /// Returns all call stacks with their costs.
+    /// Frames of a stack go from the innermost to the outermost.
+    /// The root is omitted unless it has its own costs.
+    pub fn samples(&self) -> Vec<(Vec<Frame>, Cost)> {
+        let mut samples = Vec::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            for (pc, cost) in &node.costs {
+                let mut frames = vec![(node.address, *pc)];
+                let mut i = index;
+                while i != ROOT {
+                    let n = &self.nodes[i];
+                    frames.push((self.nodes[n.parent].address, n.caller_pc));
+                    i = n.parent;
+                }
+                if frames.len() > 1 {
+                    frames.pop();
+                }
+                samples.push((frames, *cost));
+            }
+        }
+        samples
+    }
+}
@@ -0,0 +1,49 @@
+//! bpf-profile-generate summary module.
+//! Implements the summary of a profile by names of functions, used to compare profiles.
+
+use crate::config::Cost;
+use std::collections::BTreeMap;
+
+/// Represents total costs of a function (or of calls between two functions).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Totals {
+    /// Number of instructions executed by the function itself.
+    pub self_cost: Cost,
+    /// Number of instructions executed by the function and its callees.
+    /// Recursive calls are not counted twice.
+    pub inclusive_cost: Cost,
+    /// Number of calls.
+    pub calls: usize,
+}
+
+/// Represents costs of a profile by names of functions.
+/// Different copies of a function with the same name are merged.
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// Total number of executed instructions.
+    pub total_cost: Cost,
+    /// Costs of functions by their names.
+    pub functions: BTreeMap<String, Totals>,
+    /// Costs of calls by names of (caller, callee).
+    pub calls: BTreeMap<(String, String), Totals>,
+}
+
+impl Summary {
+    /// Adds the own cost of a function.
+    pub fn add_self_cost(&mut self, name: String, cost: Cost) {
+        self.total_cost += cost;
+        self.functions.entry(name).or_default().self_cost += cost;
+    }
+
+    /// Adds a finished call of the callee with its inclusive cost.
+    pub fn add_call(&mut self, caller: Option<String>, callee: String, inclusive_cost: Cost) {
+        let totals = self.functions.entry(callee.clone()).or_default();
+        totals.inclusive_cost += inclusive_cost;
+        totals.calls += 1;
+        if let Some(caller) = caller {
+            let totals = self.calls.entry((caller, callee)).or_default();
+            totals.inclusive_cost += inclusive_cost;
+            totals.calls += 1;
+        }
+    }
+}
@@ -36,72 +36,72 @@ pub const SIMPLE_CALLGRIND_INTEGRAL: &[u8] = b"# callgrind format
 version: 1
 creator: bpf-profile
 positions: line
-events: Instructions
-totals: 25
+events: Instructions ComputeUnits Loads Stores ALU Branches Calls MulDiv
+totals: 25 25 0 0 11 0 14 0
 fl=<none>
 
 fn=function_0 (0x100)
-4 6
+4 6 6 0 0 1 0 5 0
 cfn=function_1 (0x200)
 calls=1 0x200
-5 8
+5 8 8 0 0 3 0 5 0
 cfn=function_2 (0x300)
 calls=3 0x300
-6 6
+6 6 6 0 0 3 0 3 0
 
 fn=function_1 (0x200)
-20 4
+20 4 4 0 0 1 0 3 0
 cfn=function_2 (0x300)
 calls=2 0x300
-21 4
+21 4 4 0 0 2 0 2 0
 
 fn=function_2 (0x300)
-30 10
+30 10 10 0 0 5 0 5 0
 ";
 
 pub const SIMPLE_CALLGRIND_LINE_BY_LINE: &[u8] = b"# callgrind format
 version: 1
 creator: bpf-profile
 positions: line
-events: Instructions
-totals: 25
+events: Instructions ComputeUnits Loads Stores ALU Branches Calls MulDiv
+totals: 25 25 0 0 11 0 14 0
 fl=/tmp/generate_line_by_line.asm
 
 fn=function_0 (0x100)
-4 1
-5 1
-6 1
-7 1
-8 1
-9 1
+4 1 1 0 0 1 0 0 0
+5 1 1 0 0 0 0 1 0
+6 1 1 0 0 0 0 1 0
+7 1 1 0 0 0 0 1 0
+8 1 1 0 0 0 0 1 0
+9 1 1 0 0 0 0 1 0
 cfn=function_1 (0x200)
 calls=1 0x200
-5 8
+5 8 8 0 0 3 0 5 0
 cfn=function_2 (0x300)
 calls=1 0x300
-6 2
+6 2 2 0 0 1 0 1 0
 cfn=function_2 (0x300)
 calls=1 0x300
-7 2
+7 2 2 0 0 1 0 1 0
 cfn=function_2 (0x300)
 calls=1 0x300
-8 2
+8 2 2 0 0 1 0 1 0
 
 fn=function_1 (0x200)
-20 1
-21 1
-22 1
-23 1
+20 1 1 0 0 1 0 0 0
+21 1 1 0 0 0 0 1 0
+22 1 1 0 0 0 0 1 0
+23 1 1 0 0 0 0 1 0
 cfn=function_2 (0x300)
 calls=1 0x300
-21 2
+21 2 2 0 0 1 0 1 0
 cfn=function_2 (0x300)
 calls=1 0x300
-22 2
+22 2 2 0 0 1 0 1 0
 
 fn=function_2 (0x300)
-30 5
-31 5
+30 5 5 0 0 5 0 0 0
+31 5 5 0 0 0 0 5 0
 ";
 
 pub const SIMPLE_GENERATED_ASM: &str = r"1:        aaa
@@ -136,3 +136,39 @@ pub const SIMPLE_GENERATED_ASM: &str = r"1:        aaa
 30:        zzz        ; function_2 (0x300)
 31:        exit
 ";
+
+pub const SIMPLE_FOLDED: &[u8] = b"entrypoint 5
+entrypoint;function_0 (0x100) 6
+entrypoint;function_0 (0x100);function_1 (0x200) 4
+entrypoint;function_0 (0x100);function_1 (0x200);function_2 (0x300) 4
+entrypoint;function_0 (0x100);function_2 (0x300) 6
+";
+
+pub const SYSCALL_INPUT: &[u8] = b"
+# Function 0x100 calls the syscall sol_log_ (hash 0x207559bd) which has no traced body.
+[Z TRACE bpf] BPF Program Instruction Trace:
+ 1 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 1: aaa
+ 2 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 2: call 0x100
+ 3 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 20: call 0x207559bd
+ 4 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 21: bbb
+ 5 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 22: exit
+ 6 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 3: ccc
+";
+
+pub const SYSCALL_CALLGRIND: &[u8] = b"# callgrind format
+version: 1
+creator: bpf-profile
+positions: line
+events: Instructions ComputeUnits Loads Stores ALU Branches Calls MulDiv
+totals: 6 106 0 0 3 0 3 0
+fl=<none>
+
+fn=function_0 (0x100)
+20 3 3 0 0 1 0 2 0
+cfn=sol_log_
+calls=1 0x207559bd
+20 0 100 0 0 0 0 0 0
+
+fn=sol_log_
+20 0 100 0 0 0 0 0 0
+";
@@ -2,7 +2,9 @@
 
 mod mock;
 
+use crate::tests::{temp_path, TempPath};
 use crate::{config, gen::trace, resolver};
+use std::collections::BTreeMap;
 use std::fs;
 use std::io::Cursor;
 use std::path::Path;
@@ -26,7 +28,7 @@ fn generate_integral() {
     //println!("{}", std::str::from_utf8(&output).unwrap());
     //=======================================================
 
-    assert_eq!(output.len(), 317);
+    assert_eq!(output.len(), 471);
     assert_eq!(output, mock::SIMPLE_CALLGRIND_INTEGRAL);
 }
 
@@ -36,7 +38,8 @@ fn generate_line_by_line() {
     let reader = Cursor::new(mock::SIMPLE_INPUT);
     let asm_name = "/tmp/generate_line_by_line.asm".to_owned();
     let asm = Path::new(&asm_name);
-    let prof = trace::Profile::new(resv, Some(&asm));
+    let _asm_file = TempPath::new(asm);
+    let prof = trace::Profile::new(resv, Some(asm));
     assert!(prof.is_ok());
 
     let mut prof = prof.unwrap();
@@ -51,10 +54,10 @@ fn generate_line_by_line() {
     //println!("{}", std::str::from_utf8(&output).unwrap());
     //=======================================================
 
-    assert_eq!(output.len(), 504);
+    assert_eq!(output.len(), 825);
     assert_eq!(output, mock::SIMPLE_CALLGRIND_LINE_BY_LINE);
 
-    let asm = fs::read(&asm).unwrap();
+    let asm = fs::read(asm).unwrap();
     let asm = std::str::from_utf8(&asm).unwrap();
 
     //==== do not delete ====================================
@@ -65,6 +68,341 @@ fn generate_line_by_line() {
     assert_eq!(asm, mock::SIMPLE_GENERATED_ASM);
 }
 
+/// Checks the output of a format, given the name of the assembly listing.
+type Check = fn(&[u8], &str);
+
+#[test]
+fn generate_formats() {
+    let asm = temp_path("generate_formats.asm");
+    let asm_name = asm.to_str().unwrap();
+    let checks: &[(&str, Check)] = &[
+        ("pprof", check_pprof),
+        ("folded", check_folded),
+        ("speedscope", check_speedscope),
+        ("chrome", check_chrome),
+        ("lcov", check_lcov),
+    ];
+    for (format, check) in checks {
+        let resv = resolver::Resolver::default();
+        let mut prof = trace::Profile::new(resv, Some(asm.as_ref())).unwrap();
+        trace::parse(Cursor::new(mock::SIMPLE_INPUT), &mut prof).unwrap();
+
+        let mut output = Vec::<u8>::new();
+        prof.write(format, &mut output, asm_name).unwrap();
+        check(&output, asm_name);
+    }
+}
+
+/// Checks the gzipped protobuf of the pprof format.
+fn check_pprof(output: &[u8], asm_name: &str) {
+    use std::io::Read;
+    let mut decoded = Vec::<u8>::new();
+    flate2::read::GzDecoder::new(output)
+        .read_to_end(&mut decoded)
+        .unwrap();
+    let profile = decode(&decoded);
+
+    let strings = fields(&profile, 6)
+        .map(|s| std::str::from_utf8(s.bytes()).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(strings[0], "");
+    let string = |field: Option<&Field>| strings[field.unwrap().varint() as usize];
+
+    let sample_type = decode(fields(&profile, 1).next().unwrap().bytes());
+    assert_eq!(string(fields(&sample_type, 1).next()), "instructions");
+    assert_eq!(string(fields(&sample_type, 2).next()), "count");
+
+    let mut functions = BTreeMap::new();
+    for function in fields(&profile, 5) {
+        let function = decode(function.bytes());
+        let id = fields(&function, 1).next().unwrap().varint();
+        functions.insert(id, string(fields(&function, 2).next()));
+        assert_eq!(string(fields(&function, 4).next()), asm_name);
+    }
+    assert!(functions.values().any(|name| *name == "function_2 (0x300)"));
+
+    let mut locations = BTreeMap::new();
+    for location in fields(&profile, 4) {
+        let location = decode(location.bytes());
+        let id = fields(&location, 1).next().unwrap().varint();
+        let line = decode(fields(&location, 4).next().unwrap().bytes());
+        let function_id = fields(&line, 1).next().unwrap().varint();
+        assert!(functions.contains_key(&function_id));
+        locations.insert(id, function_id);
+    }
+
+    let mut total = 0;
+    for sample in fields(&profile, 2) {
+        let sample = decode(sample.bytes());
+        let ids = packed(fields(&sample, 1).next().unwrap().bytes());
+        assert!(ids.iter().all(|id| locations.contains_key(id)));
+        total += packed(fields(&sample, 2).next().unwrap().bytes())[0];
+    }
+    assert_eq!(total, 25);
+}
+
+/// Checks the folded stacks: all of them start with the entrypoint.
+fn check_folded(output: &[u8], _: &str) {
+    let parse = |text: &[u8]| {
+        std::str::from_utf8(text)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let (stack, cost) = line.rsplit_once(' ').unwrap();
+                (stack.to_string(), cost.parse::<usize>().unwrap())
+            })
+            .collect::<BTreeMap<_, _>>()
+    };
+    let stacks = parse(output);
+    assert!(stacks.keys().all(|stack| stack.starts_with("entrypoint")));
+    assert_eq!(stacks.values().sum::<usize>(), 25);
+    assert_eq!(stacks, parse(mock::SIMPLE_FOLDED));
+}
+
+/// Checks the evented profile of the speedscope format.
+fn check_speedscope(output: &[u8], _: &str) {
+    let document: serde_json::Value = serde_json::from_slice(output).unwrap();
+    let frames = document["shared"]["frames"].as_array().unwrap();
+    assert_eq!(frames.len(), 3);
+    let profiles = document["profiles"].as_array().unwrap();
+    assert_eq!(profiles.len(), 1);
+    assert_eq!(profiles[0]["name"], "function_0 (0x100)");
+    assert_eq!(profiles[0]["startValue"], 8);
+    assert_eq!(profiles[0]["endValue"], 28);
+    let events = profiles[0]["events"].as_array().unwrap();
+    assert_eq!(events.len(), 14);
+    let mut open = Vec::new();
+    for event in events {
+        let frame = event["frame"].as_u64().unwrap();
+        assert!((frame as usize) < frames.len());
+        match event["type"].as_str().unwrap() {
+            "O" => open.push(frame),
+            _ => assert_eq!(open.pop(), Some(frame)),
+        }
+    }
+    assert!(open.is_empty());
+}
+
+/// Checks the timeline of the Chrome Trace Event format.
+fn check_chrome(output: &[u8], _: &str) {
+    let document: serde_json::Value = serde_json::from_slice(output).unwrap();
+    let events = document["traceEvents"].as_array().unwrap();
+    assert_eq!(events.len(), 15);
+    assert_eq!(events[0]["ph"], "M");
+    assert_eq!(events[1]["ts"], 3);
+    assert_eq!(events[3]["name"], "function_2 (0x300)");
+    assert_eq!(events[14]["ts"], 23);
+    let mut open = Vec::new();
+    for event in &events[1..] {
+        match event["ph"].as_str().unwrap() {
+            "B" => open.push(event["name"].clone()),
+            "E" => assert!(open.pop().is_some()),
+            ph => panic!("Unexpected event {}", ph),
+        }
+    }
+    assert!(open.is_empty());
+}
+
+/// Checks the lcov tracefile: one record of the assembly listing.
+fn check_lcov(output: &[u8], asm_name: &str) {
+    let text = std::str::from_utf8(output).unwrap();
+    let mut lines = text.lines();
+    assert_eq!(lines.next(), Some("TN:"));
+    assert_eq!(lines.next(), Some(format!("SF:{}", asm_name).as_str()));
+    let mut functions = BTreeMap::new();
+    let mut counts = BTreeMap::new();
+    let mut executed = BTreeMap::new();
+    let mut totals = BTreeMap::new();
+    for line in lines.by_ref().take_while(|line| *line != "end_of_record") {
+        let (key, value) = line.split_once(':').unwrap();
+        let (a, b) = value.split_once(',').unwrap_or((value, ""));
+        let number = |s: &str| s.parse::<usize>().unwrap();
+        match key {
+            "FN" => functions.insert(b.to_string(), number(a)),
+            "FNDA" => counts.insert(b.to_string(), number(a)),
+            "DA" => executed.insert(number(a), number(b)),
+            _ => totals.insert(key.to_string(), number(a)),
+        };
+    }
+    assert!(lines.next().is_none());
+    assert_eq!(functions["function_2 (0x300)"], 30);
+    assert_eq!(counts["function_2 (0x300)"], 5);
+    assert_eq!(executed[&30], 5);
+    assert_eq!(totals["FNF"], functions.len());
+    assert_eq!(totals["FNH"], counts.values().filter(|c| **c > 0).count());
+    assert_eq!(totals["LF"], executed.len());
+    assert_eq!(totals["LH"], executed.values().filter(|c| **c > 0).count());
+    assert_eq!(executed.len(), 17);
+}
+
+/// Represents a field of a protobuf message: an integer or bytes.
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+impl Field<'_> {
+    fn varint(&self) -> u64 {
+        match self {
+            Field::Varint(value) => *value,
+            Field::Bytes(_) => panic!("Expected varint"),
+        }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match self {
+            Field::Bytes(bytes) => bytes,
+            Field::Varint(_) => panic!("Expected bytes"),
+        }
+    }
+}
+
+/// Decodes fields of a protobuf message with their numbers.
+fn decode(mut buf: &[u8]) -> Vec<(u64, Field<'_>)> {
+    let mut result = Vec::new();
+    while !buf.is_empty() {
+        let key = read_varint(&mut buf);
+        let field = match key & 7 {
+            0 => Field::Varint(read_varint(&mut buf)),
+            2 => {
+                let len = read_varint(&mut buf) as usize;
+                let (bytes, rest) = buf.split_at(len);
+                buf = rest;
+                Field::Bytes(bytes)
+            }
+            wire_type => panic!("Unexpected wire type {}", wire_type),
+        };
+        result.push((key >> 3, field));
+    }
+    result
+}
+
+/// Decodes a packed repeated integer field.
+fn packed(mut buf: &[u8]) -> Vec<u64> {
+    let mut result = Vec::new();
+    while !buf.is_empty() {
+        result.push(read_varint(&mut buf));
+    }
+    result
+}
+
+fn read_varint(buf: &mut &[u8]) -> u64 {
+    let mut value = 0;
+    for (i, byte) in buf.iter().enumerate() {
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            *buf = &buf[i + 1..];
+            return value;
+        }
+    }
+    panic!("Truncated varint");
+}
+
+/// Returns fields of the message with the number.
+fn fields<'a, 'b>(
+    message: &'b [(u64, Field<'a>)],
+    number: u64,
+) -> impl Iterator<Item = &'b Field<'a>> {
+    message
+        .iter()
+        .filter(move |(n, _)| *n == number)
+        .map(|(_, field)| field)
+}
+
+#[test]
+fn generate_syscall() {
+    let resv = resolver::Resolver::default();
+    let reader = Cursor::new(mock::SYSCALL_INPUT);
+    let prof = trace::Profile::new(resv, None);
+    assert!(prof.is_ok());
+
+    let mut prof = prof.unwrap();
+    let r = trace::parse(reader, &mut prof);
+    assert!(r.is_ok());
+
+    let mut output = Vec::<u8>::new();
+    let r = prof.write_callgrind(&mut output, config::DEFAULT_ASM);
+    assert!(r.is_ok());
+
+    //==== do not delete ====================================
+    //println!("{}", std::str::from_utf8(&output).unwrap());
+    //=======================================================
+
+    assert_eq!(output.len(), 305);
+    assert_eq!(output, mock::SYSCALL_CALLGRIND);
+}
+
+#[test]
+fn generate_summary() {
+    let resv = resolver::Resolver::default();
+    let reader = Cursor::new(mock::SIMPLE_INPUT);
+    let mut prof = trace::Profile::new(resv, None).unwrap();
+    trace::parse(reader, &mut prof).unwrap();
+
+    let summary = prof.summary();
+    assert_eq!(summary.total_cost, 20);
+    let totals = |self_cost, inclusive_cost, calls| super::Totals {
+        self_cost,
+        inclusive_cost,
+        calls,
+    };
+    let functions = summary.functions.values().copied().collect::<Vec<_>>();
+    assert_eq!(
+        functions,
+        vec![totals(6, 20, 1), totals(4, 8, 1), totals(10, 10, 5)]
+    );
+    let calls = summary.calls.values().copied().collect::<Vec<_>>();
+    assert_eq!(
+        calls,
+        vec![totals(0, 8, 1), totals(0, 6, 3), totals(0, 4, 2)]
+    );
+}
+
+#[test]
+fn folded_frame_name() {
+    use crate::gen::folded::frame_name;
+    assert_eq!(
+        frame_name("<[u8; 32] as core::convert::AsRef<[u8]>>::as_ref"),
+        "<[u8, 32] as core::convert::AsRef<[u8]>>::as_ref"
+    );
+    assert_eq!(frame_name("function_0 (0x100)"), "function_0 (0x100)");
+}
+
+#[test]
+fn generate_lcov_without_source() {
+    let resv = resolver::Resolver::default();
+    let reader = Cursor::new(mock::SIMPLE_INPUT);
+    let mut prof = trace::Profile::new(resv, None).unwrap();
+    trace::parse(reader, &mut prof).unwrap();
+
+    let mut output = Vec::<u8>::new();
+    let r = prof.write_lcov(&mut output, config::DEFAULT_ASM);
+    assert!(matches!(r, Err(crate::error::Error::LcovWithoutSource)));
+    assert!(output.is_empty());
+}
+
+#[test]
+fn generate_lcov_without_source_file() {
+    let trace_path = temp_path("lcov.trace");
+    fs::write(&trace_path, mock::SIMPLE_INPUT).unwrap();
+    let output_path = temp_path("lcov.info");
+    for split in [false, true] {
+        let r = super::run(
+            &trace_path,
+            None,
+            None,
+            &config::Config::default(),
+            "lcov",
+            Some(&output_path),
+            split,
+            false,
+        );
+        assert!(matches!(r, Err(crate::error::Error::LcovWithoutSource)));
+        assert!(!output_path.exists());
+        assert!(!super::numbered(&output_path, 1).exists());
+    }
+}
+
 #[test]
 fn subsequence() {
     let r = find_subsequence(b"lorem ipsum dolor sit amet", b"dolor");
@@ -1,11 +1,15 @@
 //! bpf-profile-generate trace module.
 //! Implements parsing of the trace file and generating the profile.
 
-use super::asm;
-use super::profile::{self, Call, Function, Functions};
-use crate::config::{Cost, Map, ProgramCounter, GROUND_ZERO};
-use crate::error::{Error, Result};
+use super::events::Events;
+use super::profile::{self, Call, Counters, Function, Functions};
+use super::stacks::Stacks;
+use super::summary::Summary;
+use super::{asm, chrome, folded, lcov, pprof, speedscope};
+use crate::config::{Address, Config, Cost, Map, ProgramCounter, GROUND_ZERO};
+use crate::error::{recover, Error, Result};
 use crate::resolver::{self, Resolver};
+use crate::trace::{for_each_section, Step, Walker};
 use crate::{filebuf, global};
 use std::io::{BufRead, Write};
 use std::path::Path;
@@ -13,9 +17,15 @@ use std::path::Path;
 /// Represents the profile.
 #[derive(Debug)]
 pub struct Profile {
-    total_cost: Cost,
+    totals: Counters,
     ground: Call,
+    invocation_depth: usize,
+    ignored_depth: usize,
+    line: usize,
+    lenient: bool,
     functions: Functions,
+    stacks: Stacks,
+    events: Events,
     resolver: Resolver,
     asm: Option<asm::Source>,
 }
@@ -24,70 +34,188 @@ use crate::bpf::Instruction;
 
 impl Profile {
     /// Creates the initial instance of profile.
+    /// Settings of the resolver apply to the profile (costs, ignored functions).
     pub fn new(resv: Resolver, asm_path: Option<&Path>) -> Result<Self> {
         let mut functions = Map::new();
         functions.insert(GROUND_ZERO, Function::ground_zero());
+        let end_pc = (!resv.is_default()).then(|| resv.end_pc());
         Ok(Profile {
-            total_cost: 0,
+            totals: Counters::default(),
             ground: Call::new(GROUND_ZERO, 0),
+            invocation_depth: 0,
+            ignored_depth: 0,
+            line: 0,
+            lenient: false,
             functions,
+            stacks: Stacks::default(),
+            events: Events::default(),
             resolver: resv,
-            asm: asm_path.map(asm::Source::new),
+            asm: asm_path.map(|p| asm::Source::new(p, end_pc)),
         })
     }
 
-    /// Reads the trace and creates the profile data.
+    /// Enables or disables the lenient mode: problems of the input are logged
+    /// and skipped instead of stopping with an error.
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
+    }
+
+    /// Reads the trace (in a single pass) and creates the profile data.
+    /// Every traced invocation becomes a separate child of the root.
     pub fn create(
         trace_path: &Path,
         dump_path: Option<&Path>,
         asm_path: Option<&Path>,
+        config: &Config,
+        lenient: bool,
     ) -> Result<Self> {
         tracing::debug!("Profile.create {:?}", trace_path);
 
-        let resv = resolver::read(dump_path)?;
-        let reader = filebuf::open(trace_path)?;
+        let resv = resolver::read(dump_path, config)?;
         let mut prof = Profile::new(resv, asm_path)?;
-        parse(reader, &mut prof)?;
-
+        prof.set_lenient(lenient);
+        prof.read(filebuf::open_input(trace_path)?)?;
         Ok(prof)
     }
 
+    /// Reads all traced invocations of the log adding them to the profile.
+    /// Every traced invocation becomes a separate child of the root.
+    pub fn read(&mut self, reader: impl BufRead) -> Result<()> {
+        for_each_section(reader, |section| {
+            self.enter_invocation(section.number());
+            parse(section, self)?;
+            self.leave_invocation();
+            Ok(())
+        })
+    }
+
     /// Writes the profile data in the callgrind file format.
     /// See details of the format in the Valgrind documentation.
     pub fn write_callgrind(&self, mut output: impl Write, asm_fl: &str) -> Result<()> {
-        if self.asm.is_some() {
-            let asm = self.asm.as_ref().unwrap();
-            asm.write(&self.resolver)?;
-        }
+        self.write_asm()?;
 
         writeln!(output, "# callgrind format")?;
         writeln!(output, "version: 1")?;
         writeln!(output, "creator: bpf-profile")?;
         writeln!(output, "positions: line")?;
-        writeln!(output, "events: Instructions")?;
-        writeln!(output, "totals: {}", self.total_cost)?;
-        writeln!(output, "fl={}", asm_fl)?;
-        profile::write_callgrind_functions(output, &self.functions, self.asm.is_some())?;
+        writeln!(output, "events: {}", profile::events())?;
+        writeln!(output, "totals: {}", profile::format_counters(&self.totals))?;
+        if !self.resolver.has_source_lines() {
+            writeln!(output, "fl={}", asm_fl)?;
+        }
+        profile::write_callgrind_functions(
+            output,
+            &self.functions,
+            &self.resolver,
+            asm_fl,
+            self.asm.is_some(),
+        )?;
 
         Ok(())
     }
 
+    /// Returns true if lines of the profile refer to a source:
+    /// the generated assembly listing or the line table of the ELF file.
+    pub fn has_source(&self) -> bool {
+        self.asm.is_some() || self.resolver.has_source_lines()
+    }
+
+    /// Writes the profile data in the format (one of `config::FORMATS`).
+    pub fn write(&self, format: &str, output: impl Write, asm_fl: &str) -> Result<()> {
+        match format {
+            "callgrind" => self.write_callgrind(output, asm_fl),
+            "pprof" => self.write_pprof(output, asm_fl),
+            "folded" => self.write_folded(output),
+            "speedscope" => self.write_speedscope(output),
+            "chrome" => self.write_chrome(output),
+            "lcov" => self.write_lcov(output, asm_fl),
+            _ => Err(Error::Format(format.into())),
+        }
+    }
+
+    /// Writes the profile data in the pprof format.
+    pub fn write_pprof(&self, output: impl Write, asm_fl: &str) -> Result<()> {
+        self.write_asm()?;
+        pprof::write(output, &self.functions, &self.stacks, asm_fl)
+    }
+
+    /// Writes the profile data as folded stacks.
+    pub fn write_folded(&self, output: impl Write) -> Result<()> {
+        self.write_asm()?;
+        folded::write(output, &self.functions, &self.stacks, &self.resolver)
+    }
+
+    /// Writes the profile data in the speedscope format.
+    pub fn write_speedscope(&self, output: impl Write) -> Result<()> {
+        self.write_asm()?;
+        speedscope::write(output, &self.functions, &self.events)
+    }
+
+    /// Writes the profile data in the Chrome Trace Event format.
+    pub fn write_chrome(&self, output: impl Write) -> Result<()> {
+        self.write_asm()?;
+        chrome::write(output, &self.functions, &self.events)
+    }
+
+    /// Writes execution counts of lines as an lcov tracefile.
+    /// Requires either the generated assembly listing or the line table.
+    pub fn write_lcov(&self, output: impl Write, asm_fl: &str) -> Result<()> {
+        if !self.has_source() {
+            return Err(Error::LcovWithoutSource);
+        }
+        self.write_asm()?;
+        lcov::write(output, &self.functions, &self.resolver, asm_fl)
+    }
+
+    /// Returns the summary of costs by names of functions.
+    pub fn summary(&self) -> Summary {
+        profile::summarize(&self.functions, &self.resolver)
+    }
+
+    /// Writes the generated assembly listing (if any).
+    fn write_asm(&self) -> Result<()> {
+        if let Some(asm) = &self.asm {
+            asm.write(&self.resolver, self.lenient)?;
+        }
+        Ok(())
+    }
+
     /// Adds instruction to the generated assembly listing.
-    fn keep_asm(&mut self, ix: &Instruction) {
-        let _ = self.asm.as_mut().map(|a| a.add_instruction(ix));
+    fn keep_asm(&mut self, ix: &Instruction, lc: usize) -> Result<()> {
+        match self.asm.as_mut() {
+            Some(asm) => asm.add_instruction(ix, lc),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns number of executed instructions, which is the time of events.
+    fn instructions(&self) -> Cost {
+        self.totals[0]
     }
 
     /// Increments the total cost and the cost of current call.
-    fn increment_cost(&mut self, pc: ProgramCounter) {
+    fn increment_cost(&mut self, ix: &Instruction) {
         tracing::debug!("Profile.increment_cost");
-        self.total_cost += 1;
-        self.ground.increment_cost(pc, &mut self.functions);
+        let units = self.resolver.config().instruction_units(ix.class());
+        let counters = profile::instruction_counters(ix, units);
+        profile::add_counters(&mut self.totals, &counters);
+        self.ground
+            .increment_cost(ix.pc(), &counters, &mut self.functions);
+        self.stacks.increment_cost(ix.pc());
     }
 
-    /// Adds next call to the call stack.
-    fn push_call(&mut self, call: Call, first_pc: ProgramCounter) {
+    /// Adds next call (at line `lc`) to the call stack.
+    /// Calls of ignored functions (and everything they call) are left
+    /// to the caller.
+    fn push_call(&mut self, call: Call, first_pc: ProgramCounter, lc: usize) {
         let address = call.address();
+        if self.ignored_depth > 0 || self.is_ignored(address, first_pc) {
+            self.ignored_depth += 1;
+            return;
+        }
         tracing::debug!("Profile.push_call 0x{:x}", address);
+        self.stacks.push_call(address, call.caller_pc());
+        self.events.open(address, lc, self.instructions());
         self.ground.push_call(call);
         #[allow(clippy::map_entry)]
         if !self.functions.contains_key(&address) {
@@ -97,9 +225,73 @@ impl Profile {
         }
     }
 
+    /// Adds finished call of a syscall, which is a leaf without traced instructions.
+    /// Compute units of the syscall are charged at the pc of the call.
+    fn push_syscall(&mut self, call: Call, name: &str, lc: usize) -> Result<()> {
+        // The resolver knows the name now, so the first pc of the callee is not used
+        self.resolver.update_syscall(call.address(), name);
+        let caller_pc = call.caller_pc();
+        self.push_call(call, caller_pc, lc);
+        let units = self.resolver.config().syscall_units(name);
+        let counters = profile::syscall_counters(units);
+        profile::add_counters(&mut self.totals, &counters);
+        self.ground
+            .increment_cost(caller_pc, &counters, &mut self.functions);
+        self.pop_call(lc)
+    }
+
+    /// Opens the call which represents a traced invocation of the program.
+    pub(crate) fn enter_invocation(&mut self, number: usize) {
+        let address = profile::invocation_address(number);
+        tracing::debug!("Profile.enter_invocation {}", number);
+        self.functions
+            .entry(address)
+            .or_insert_with(|| Function::invocation(address, number));
+        self.stacks.push_call(address, 0);
+        self.events.open(address, 0, self.instructions());
+        self.ground.push_call(Call::new(address, 0));
+        self.invocation_depth = self.ground.depth();
+    }
+
+    /// Closes the call of current invocation after the last line of its section.
+    pub(crate) fn leave_invocation(&mut self) {
+        tracing::debug!("Profile.leave_invocation");
+        self.invocation_depth = 0;
+        // Exits of the section never pop the call of the invocation (see pop_call),
+        // so it is on the stack since enter_invocation
+        let call = self.ground.pop_call().expect("Missing call of invocation");
+        self.add_call(call, self.line);
+    }
+
+    /// Checks if the config file asks to ignore the called function.
+    fn is_ignored(&mut self, address: Address, first_pc: ProgramCounter) -> bool {
+        let name = self.resolver.update(address, first_pc);
+        self.resolver.config().is_ignored(&name)
+    }
+
     /// Removes finished call from the call stack and adds it to the caller.
-    fn pop_call(&mut self) {
-        let call = self.ground.pop_call();
+    /// Returns an error if the exit at line `lc` has no matching call.
+    fn pop_call(&mut self, lc: usize) -> Result<()> {
+        if self.ignored_depth > 0 {
+            self.ignored_depth -= 1;
+            return Ok(());
+        }
+        if self.invocation_depth > 0 && self.ground.depth() == self.invocation_depth {
+            // Final exit of the program's entrypoint, the invocation lasts until its end
+            return Ok(());
+        }
+        let call = self
+            .ground
+            .pop_call()
+            .ok_or(Error::TraceExitWithoutCall(lc))?;
+        self.add_call(call, lc);
+        Ok(())
+    }
+
+    /// Adds the call removed from the call stack (at line `lc`) to its caller.
+    fn add_call(&mut self, call: Call, lc: usize) {
+        self.stacks.pop_call();
+        self.events.close(lc, self.instructions());
         tracing::debug!("Profile.pop_call 0x{:x}", &call.address());
         if !call.is_ground() {
             let f = self
@@ -111,67 +303,46 @@ impl Profile {
     }
 }
 
-/// Parses the trace file line by line, building the Profile instance.
-pub fn parse(mut reader: impl BufRead, prof: &mut Profile) -> Result<()> {
+/// Parses one section of the trace line by line, building the Profile instance.
+pub fn parse(reader: impl BufRead, prof: &mut Profile) -> Result<()> {
     if global::verbose() {
         tracing::info!("Parsing trace file, creating profile...")
     }
 
-    let mut line = String::with_capacity(512);
-    let mut bytes_read = usize::MAX;
-    let mut lc = 0_usize;
-    let mut ix: Instruction;
-
-    while bytes_read != 0 {
-        if line.is_empty() {
-            bytes_read = filebuf::read_line(&mut reader, &mut line)?;
-            lc += 1;
-        }
-
-        let ixr = Instruction::parse(&line);
-        if let Err(Error::TraceSkipped) = &ixr {
-            /* warn!("Skip '{}'", &line.trim()); */
-            line.clear();
-            continue;
-        }
-        ix = ixr?;
-
-        prof.keep_asm(&ix);
-
-        if ix.is_exit() {
-            prof.increment_cost(ix.pc());
-            prof.pop_call();
-            line.clear();
-            continue;
-        }
-
-        if !ix.is_call() {
-            prof.increment_cost(ix.pc());
-            line.clear();
-            continue;
-        }
-
-        // Handle sequences of enclosed calls as well:
-        // 604: call 0xcb3fc071
-        // 588: call 0x8e0001f9
-        // 1024: call 0x8bf38212
-        // ...
-        while ix.is_call() {
-            prof.increment_cost(ix.pc());
-            let call = Call::from(&ix, lc)?;
-            // Read next line — the first instruction of the call
-            bytes_read = filebuf::read_line(&mut reader, &mut line)?;
-            lc += 1;
-            ix = Instruction::parse(&line)?;
-            prof.push_call(call, ix.pc());
+    let lenient = prof.lenient;
+    let mut walker = Walker::new(reader, prof.resolver.config().clone(), lenient);
+    for step in &mut walker {
+        match step? {
+            Step::Instruction { ix, lc } => {
+                recover(prof.keep_asm(&ix, lc), lenient)?;
+                prof.increment_cost(&ix);
+            }
+            Step::Call {
+                caller_pc,
+                address,
+                first,
+                lc,
+            } => prof.push_call(Call::new(address, caller_pc), first.pc(), lc),
+            Step::Syscall {
+                caller_pc,
+                address,
+                name,
+                lc,
+            } => prof.push_syscall(Call::new(address, caller_pc), &name, lc)?,
+            Step::Exit { lc } => {
+                recover(prof.pop_call(lc), lenient)?;
+            }
         }
-        // Keep here the last non-call line to process further
     }
 
-    if prof.ground.depth() > 0 {
-        tracing::warn!("Unbalanced call/exit: {}", &prof.ground.depth());
-        for _ in 0..prof.ground.depth() {
-            prof.pop_call();
+    let lc = walker.lines();
+    prof.ignored_depth = 0;
+    prof.line = lc;
+    let unbalanced = prof.ground.depth() - prof.invocation_depth;
+    if unbalanced > 0 {
+        tracing::warn!("Unbalanced call/exit: {}", unbalanced);
+        for _ in 0..unbalanced {
+            prof.pop_call(lc)?;
         }
     }
 
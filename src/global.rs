@@ -1,4 +1,5 @@
 //! bpf-profile global module.
+//! Keeps the verbosity shared by all parts of the program.
 
 use lazy_static::lazy_static;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -7,10 +8,12 @@ lazy_static! {
     static ref VERBOSE: AtomicBool = AtomicBool::default();
 }
 
+/// Enables or disables verbose messages.
 pub fn set_verbose(v: bool) {
     VERBOSE.store(v, Ordering::Relaxed);
 }
 
+/// Checks if verbose messages are enabled.
 pub fn verbose() -> bool {
     VERBOSE.load(Ordering::Relaxed)
 }
@@ -0,0 +1,74 @@
+//! bpf-profile library.
+//!
+//! Reads the trace output of the BPF VM and builds profiles of programs
+//! for tools like `callgrind_annotate`, `qcachegrind`, `pprof` or `speedscope`.
+//!
+//! The main parts are:
+//! - [`Instruction`] parses a line of the trace;
+//! - [`Sections`] splits a log into traced invocations;
+//! - [`Resolver`] maps addresses and program counters to names of functions
+//!   and source lines, reading a dump or an ELF file;
+//! - [`Profile`] accumulates costs of calls and writes them in output formats.
+//!
+//! ```no_run
+//! use bpf_profile::{open_input, Config, Profile, Resolver};
+//! use std::path::Path;
+//!
+//! # fn main() -> bpf_profile::Result<()> {
+//! let config = Config::read(Some(Path::new("bpf-profile.conf")))?;
+//! let resv = Resolver::read(Path::new("program.so"), &config)?;
+//! let mut profile = Profile::new(resv, None)?;
+//! profile.read(open_input(Path::new("program.trace"))?)?;
+//! profile.write_callgrind(std::io::stdout(), "<none>")?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Settings of the config file are passed to the [`Resolver`]
+//! and apply to profiles built with it.
+//! The [`commands`] of the `bpf-profile` program are available as functions.
+
+#![deny(unsafe_code)]
+#![deny(missing_docs)]
+
+mod bpf;
+mod calls;
+mod config;
+mod coverage;
+mod diff;
+mod error;
+mod filebuf;
+mod gen;
+mod global;
+mod memory;
+mod resolver;
+mod stack;
+mod top;
+mod trace;
+
+#[cfg(test)]
+mod tests;
+
+pub use bpf::Instruction;
+pub use config::Config;
+pub use error::{Error, Result};
+pub use filebuf::open_input;
+pub use gen::{Profile, Summary, Totals};
+pub use global::set_verbose;
+pub use resolver::Resolver;
+pub use trace::{Section, Sections};
+
+/// Commands of the `bpf-profile` program and defaults of their options.
+/// Each command reads the trace and prints a report or writes a profile.
+pub mod commands {
+    pub use crate::calls::run as calls;
+    pub use crate::config::{
+        CALLS_FORMATS, DEFAULT_CALLS_FORMAT, DEFAULT_FORMAT, DEFAULT_TAB, DEFAULT_TOP, FORMATS,
+    };
+    pub use crate::coverage::run as coverage;
+    pub use crate::diff::run as diff;
+    pub use crate::gen::run as generate;
+    pub use crate::memory::run as memory;
+    pub use crate::stack::run as stack;
+    pub use crate::top::run as top;
+}
@@ -17,25 +17,16 @@
 #![deny(unsafe_code)]
 #![deny(missing_docs)]
 
-mod bpf;
-mod calls;
 mod cli;
-mod config;
-mod error;
-mod filebuf;
-mod gen;
-mod global;
-mod resolver;
-mod trace;
 
-#[cfg(test)]
-mod tests;
+/// Exit code of the program on errors.
+const FAILURE: i32 = 1;
 
 fn main() {
     init_logger();
-    if let Err(err) = execute(cli::application()) {
+    if let Err(err) = cli::execute(cli::application()) {
         eprintln!("Error: {:#}", err);
-        std::process::exit(config::FAILURE);
+        std::process::exit(FAILURE);
     }
 }
 
@@ -46,38 +37,3 @@ fn init_logger() {
     }
     tracing_subscriber::fmt::init();
 }
-
-use crate::error::Result;
-
-/// Dispatches CLI commands.
-fn execute(app: cli::Application) -> Result<()> {
-    global::set_verbose(app.verbose);
-
-    match app.cmd {
-        cli::Command::Calls { trace, dump, tab } => {
-            calls::run(
-                &trace,
-                dump.as_ref().map(|p| p.as_ref()), // Option<T> -> Option<&T>,
-                tab,
-            )?;
-        }
-
-        cli::Command::Generate {
-            trace,
-            asm,
-            dump,
-            format,
-            output,
-        } => {
-            gen::run(
-                &trace,
-                asm.as_ref().map(|p| p.as_ref()), // Option<T> -> Option<&T>
-                dump.as_ref().map(|p| p.as_ref()), // Option<T> -> Option<&T>
-                &format,
-                output.as_ref().map(|p| p.as_ref()), // Option<T> -> Option<&T>
-            )?;
-        }
-    }
-
-    Ok(())
-}
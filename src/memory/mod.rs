@@ -0,0 +1,166 @@
+//! bpf-profile memory command implementation.
+
+use crate::bpf::{Access, Region, ENTRYPOINT};
+use crate::config::Config;
+use crate::error::Result;
+use crate::resolver::Resolver;
+use crate::trace::{for_each_section, Step, Walker};
+use crate::{filebuf, global};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+/// Reads the trace and prints bytes read and written by functions per memory region.
+pub fn run(
+    trace_path: &Path,
+    dump_path: Option<&Path>,
+    config: &Config,
+    lenient: bool,
+) -> Result<()> {
+    let mut resv = crate::resolver::read(dump_path, config)?;
+
+    let mut usage = MemoryUsage::default();
+    for_each_section(filebuf::open_input(trace_path)?, |section| {
+        usage.trace(section, &mut resv, lenient)
+    })?;
+
+    usage.write(io::stdout())
+}
+
+/// Represents numbers of bytes read and written by a function, indexed by regions.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MemoryTotals {
+    /// Bytes loaded from each region.
+    pub read: [u64; Region::ALL.len()],
+    /// Bytes stored to each region.
+    pub written: [u64; Region::ALL.len()],
+}
+
+impl MemoryTotals {
+    /// Returns number of bytes read and written in all regions.
+    pub fn total(&self) -> u64 {
+        self.read.iter().chain(self.written.iter()).sum()
+    }
+
+    /// Adds numbers of bytes of other totals.
+    fn add(&mut self, other: &MemoryTotals) {
+        for i in 0..Region::ALL.len() {
+            self.read[i] += other.read[i];
+            self.written[i] += other.written[i];
+        }
+    }
+}
+
+/// Represents memory accesses of functions of traced invocations.
+#[derive(Debug, Default)]
+pub struct MemoryUsage {
+    /// Accesses by names of functions (own instructions only, callees excluded).
+    pub functions: BTreeMap<String, MemoryTotals>,
+    /// Number of accesses to addresses outside of known regions.
+    pub unknown: usize,
+    calls: Vec<String>,
+}
+
+impl MemoryUsage {
+    /// Parses a section of the trace line by line, following calls.
+    /// Effective addresses of loads and stores are computed from registers
+    /// of the trace line and attributed to the function being executed.
+    pub fn trace(
+        &mut self,
+        reader: impl BufRead,
+        resv: &mut Resolver,
+        lenient: bool,
+    ) -> Result<()> {
+        if global::verbose() {
+            tracing::info!("Parsing trace, collecting memory accesses...")
+        }
+
+        for step in Walker::new(reader, resv.config().clone(), lenient) {
+            match step? {
+                Step::Instruction { ix, .. } => {
+                    if self.calls.is_empty() {
+                        let name = resv
+                            .resolve_by_first_pc(ix.pc())
+                            .unwrap_or_else(|| ENTRYPOINT.into());
+                        self.calls.push(name);
+                    }
+                    if let Some((access, address, size)) = ix.memory_access() {
+                        self.access(address, size as u64, access == Access::Store);
+                    }
+                }
+                Step::Call { address, first, .. } => {
+                    let name = resv.update(address, first.pc());
+                    self.calls.push(name);
+                }
+                Step::Syscall { .. } => {}
+                Step::Exit { .. } => {
+                    self.calls.pop();
+                }
+            }
+        }
+
+        // The final exit of the entrypoint may be missing in truncated traces
+        self.calls.clear();
+        Ok(())
+    }
+
+    /// Writes the table of functions sorted by numbers of accessed bytes,
+    /// with bytes read and written per region, and totals of all functions.
+    pub fn write(&self, mut output: impl Write) -> Result<()> {
+        let mut functions = self.functions.iter().collect::<Vec<_>>();
+        functions.sort_by(|(f1, t1), (f2, t2)| t2.total().cmp(&t1.total()).then(f1.cmp(f2)));
+
+        for region in Region::ALL {
+            write!(output, "{:>21}", region.name())?;
+        }
+        writeln!(output)?;
+        for _ in Region::ALL {
+            write!(output, " {:>10}{:>10}", "Read", "Written")?;
+        }
+        writeln!(output, "  Function")?;
+
+        let mut total = MemoryTotals::default();
+        for (name, totals) in functions {
+            write_totals(&mut output, totals, name)?;
+            total.add(totals);
+        }
+        write_totals(&mut output, &total, "Total")?;
+        if self.unknown != 0 {
+            writeln!(
+                output,
+                "Accesses outside of known regions: {}",
+                self.unknown
+            )?;
+        }
+
+        output.flush()?;
+        Ok(())
+    }
+
+    /// Adds an access of the current function.
+    fn access(&mut self, address: u64, size: u64, store: bool) {
+        let region = match Region::of(address) {
+            Some(region) => region as usize,
+            None => {
+                self.unknown += 1;
+                return;
+            }
+        };
+        let name = self.calls.last().map(String::as_str).unwrap_or_default();
+        let totals = self.functions.entry(name.to_string()).or_default();
+        if store {
+            totals.written[region] += size;
+        } else {
+            totals.read[region] += size;
+        }
+    }
+}
+
+/// Writes a row of the table.
+fn write_totals(output: &mut impl Write, totals: &MemoryTotals, name: &str) -> Result<()> {
+    for (read, written) in totals.read.iter().zip(totals.written.iter()) {
+        write!(output, " {:>10}{:>10}", read, written)?;
+    }
+    writeln!(output, "  {}", name)?;
+    Ok(())
+}
@@ -0,0 +1,75 @@
+//! bpf-profile resolver dwarf module.
+//! Implements reading of the line table from debug info of the program ELF file.
+
+use super::Resolver;
+use crate::bpf::INSTRUCTION_SIZE;
+use crate::config::ProgramCounter;
+use crate::error::{Error, Result};
+use gimli::{EndianSlice, FileEntry, LineProgramHeader, LittleEndian, SectionId, Unit};
+use goblin::elf::Elf;
+use std::path::PathBuf;
+
+type Reader<'a> = EndianSlice<'a, LittleEndian>;
+
+/// Parses `.debug_line` of the ELF file (if any) mapping program counters to source lines.
+pub fn parse_debug_line(elf: &Elf, bytes: &[u8], resv: &mut Resolver) -> Result<()> {
+    parse_units(elf, bytes, resv).map_err(|e| Error::DwarfParsing(e.to_string()))
+}
+
+fn parse_units(elf: &Elf, bytes: &[u8], resv: &mut Resolver) -> gimli::Result<()> {
+    let section = |id: SectionId| -> gimli::Result<Reader> {
+        let data = elf
+            .section_headers
+            .iter()
+            .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(id.name()))
+            .and_then(|sh| sh.file_range())
+            .and_then(|range| bytes.get(range))
+            .unwrap_or_default();
+        Ok(EndianSlice::new(data, LittleEndian))
+    };
+    let dwarf = gimli::Dwarf::load(section)?;
+
+    let mut units = dwarf.units();
+    while let Some(header) = units.next()? {
+        let unit = dwarf.unit(header)?;
+        let program = match unit.line_program.clone() {
+            Some(program) => program,
+            None => continue,
+        };
+        let mut rows = program.rows();
+        while let Some((header, row)) = rows.next_row()? {
+            let pc = (row.address() / INSTRUCTION_SIZE) as ProgramCounter;
+            let line = row.line().map(|l| l.get() as usize).unwrap_or(0);
+            if row.end_sequence() || line == 0 {
+                // Sequences can adjoin, so the end of one must not hide the start of the next
+                resv.source_lines.entry(pc).or_insert(None);
+                continue;
+            }
+            let file = match row.file(header) {
+                Some(file) => file_path(&dwarf, &unit, header, file)?,
+                None => continue,
+            };
+            let file_index = resv.source_file_index(&file);
+            resv.source_lines.insert(pc, Some((file_index, line)));
+        }
+    }
+    Ok(())
+}
+
+/// Returns full path of a file of the line table.
+fn file_path(
+    dwarf: &gimli::Dwarf<Reader>,
+    unit: &Unit<Reader>,
+    header: &LineProgramHeader<Reader>,
+    file: &FileEntry<Reader>,
+) -> gimli::Result<String> {
+    let mut path = PathBuf::new();
+    if let Some(dir) = &unit.comp_dir {
+        path.push(&*dir.to_string_lossy());
+    }
+    if let Some(dir) = file.directory(header) {
+        path.push(&*dwarf.attr_string(unit, dir)?.to_string_lossy());
+    }
+    path.push(&*dwarf.attr_string(unit, file.path_name())?.to_string_lossy());
+    Ok(path.to_string_lossy().into_owned())
+}
@@ -0,0 +1,153 @@
+//! bpf-profile resolver elf module.
+//! Implements reading of the program ELF file without external tools.
+
+use super::{dwarf, Resolver};
+use crate::bpf::{self, INSTRUCTION_SIZE, MM_PROGRAM_START};
+use crate::config::{ProgramCounter, PADDING};
+use crate::error::{Error, Result};
+use goblin::elf::{sym, Elf};
+use std::collections::BTreeMap;
+
+const MAGIC: &[u8] = b"\x7fELF";
+const TEXT: &str = ".text";
+
+const R_BPF_64_RELATIVE: u32 = 8;
+const R_BPF_64_32: u32 = 10;
+
+/// Checks the input starts as an ELF file.
+pub fn is_elf(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Parses the ELF file building the Resolver instance.
+/// Uses `.symtab` for names of functions, `.text` and dynamic relocations
+/// for the pretty listing, `.debug_line` (if any) for source lines.
+pub fn parse_elf_file(bytes: &[u8], resv: &mut Resolver) -> Result<()> {
+    let elf = Elf::parse(bytes).map_err(|e| Error::ElfParsing(e.to_string()))?;
+
+    let (text_index, text) = elf
+        .section_headers
+        .iter()
+        .enumerate()
+        .find(|(_, sh)| elf.shdr_strtab.get_at(sh.sh_name) == Some(TEXT))
+        .ok_or(Error::ElfNoText)?;
+    let text_bytes = text
+        .file_range()
+        .and_then(|range| bytes.get(range))
+        .ok_or_else(|| Error::ElfParsing("invalid .text section".into()))?;
+
+    // Collect functions and labels of basic blocks sorted by pc
+    let mut functions = Vec::new();
+    let mut labels = BTreeMap::new();
+    for s in elf.syms.iter() {
+        if s.st_shndx != text_index {
+            continue;
+        }
+        let name = match elf.strtab.get_at(s.st_name) {
+            Some(name) if !name.is_empty() => name,
+            _ => continue,
+        };
+        let pc = to_pc(s.st_value);
+        if name.starts_with("LBB") {
+            labels.insert(pc, name.to_string());
+        } else if s.st_type() == sym::STT_FUNC {
+            functions.push((pc, name));
+        }
+    }
+    functions.sort();
+
+    for (pc, function) in functions {
+        resv.add_function(function, pc);
+    }
+    for (pc, label) in &labels {
+        resv.add_label(*pc, label);
+    }
+
+    // Collect relocations applied by the loader
+    let mut relocations = BTreeMap::new();
+    for r in elf.dynrels.iter().chain(elf.dynrelas.iter()) {
+        let name = elf
+            .dynsyms
+            .get(r.r_sym)
+            .and_then(|s| elf.dynstrtab.get_at(s.st_name))
+            .unwrap_or_default();
+        relocations.insert(to_pc(r.r_offset), (r.r_type, name.to_string()));
+    }
+
+    // Disassemble the code
+    let first_pc = to_pc(text.sh_addr);
+    let mut offset = 0_usize;
+    while offset < text_bytes.len() {
+        let pc = first_pc + offset / INSTRUCTION_SIZE as usize;
+        let end = std::cmp::min(offset + 2 * INSTRUCTION_SIZE as usize, text_bytes.len());
+        let mut ix = text_bytes[offset..end].to_vec();
+        let mut comments = Vec::new();
+        if let Some(label) = labels.get(&pc) {
+            comments.push(label.clone());
+        }
+        match relocations.get(&pc) {
+            Some((R_BPF_64_RELATIVE, _)) => relocate(&mut ix),
+            Some((R_BPF_64_32, name)) if !name.is_empty() => comments.push(name.clone()),
+            _ => {
+                if let Some(function) =
+                    call_target(&ix, pc).and_then(|t| resv.resolve_by_first_pc(t))
+                {
+                    comments.push(function);
+                }
+            }
+        }
+
+        let (text, size) = bpf::disassemble(&ix);
+        resv.add_pretty_source(
+            pc,
+            if comments.is_empty() {
+                format!("{}:{}{}", pc, PADDING, &text)
+            } else {
+                format!(
+                    "{}:{}{}{}; {}",
+                    pc,
+                    PADDING,
+                    &text,
+                    PADDING,
+                    comments.join(", ")
+                )
+            },
+        );
+        offset += size;
+    }
+
+    dwarf::parse_debug_line(&elf, bytes, resv)?;
+
+    resv.compress();
+    Ok(())
+}
+
+/// Converts a virtual address of the ELF to program counter.
+fn to_pc(address: u64) -> ProgramCounter {
+    (address / INSTRUCTION_SIZE) as ProgramCounter
+}
+
+/// Adds start of the program region to the immediate of `lddw`, as the loader does.
+fn relocate(ix: &mut [u8]) {
+    if ix.len() < 16 || ix[0] != 0x18 {
+        return;
+    }
+    let low = u32::from_le_bytes([ix[4], ix[5], ix[6], ix[7]]) as u64;
+    let high = u32::from_le_bytes([ix[12], ix[13], ix[14], ix[15]]) as u64;
+    let mut address = high << 32 | low;
+    if address < MM_PROGRAM_START {
+        address += MM_PROGRAM_START;
+    }
+    ix[4..8].copy_from_slice(&(address as u32).to_le_bytes());
+    ix[12..16].copy_from_slice(&((address >> 32) as u32).to_le_bytes());
+}
+
+/// Returns pc of the target of a call relative to the instruction (if any).
+fn call_target(ix: &[u8], pc: ProgramCounter) -> Option<ProgramCounter> {
+    if ix.len() < 8 || ix[0] != 0x85 || ix[1] >> 4 != 1 {
+        return None;
+    }
+    let imm = i32::from_le_bytes([ix[4], ix[5], ix[6], ix[7]]) as i64;
+    let target = pc as i64 + imm + 1;
+    (target >= 0).then_some(target as ProgramCounter)
+}
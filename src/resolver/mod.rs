@@ -1,21 +1,29 @@
 //! bpf-profile resolver module.
 
-use crate::config::{Address, Index, Map, ProgramCounter, GROUND_ZERO, PADDING};
+mod dwarf;
+mod elf;
+pub mod names;
+
+use crate::bpf::INSTRUCTION_SIZE;
+use crate::config::{Address, Config, Index, Map, ProgramCounter, GROUND_ZERO, PADDING};
 use crate::error::{Error, Result};
 use crate::{filebuf, global};
+use std::collections::BTreeMap;
+use std::fs;
 use std::io::{BufRead, Write};
 use std::path::Path;
+use std::sync::Arc;
 
-/// Reads the dump file (if any) and returns a dump representation.
-pub fn read(filepath: Option<&Path>) -> Result<Resolver> {
+/// Reads the dump file or the ELF file (if any) and returns a dump representation.
+pub fn read(filepath: Option<&Path>, config: &Config) -> Result<Resolver> {
     match filepath {
-        None => Ok(Resolver::default()),
-        Some(path) => Resolver::read(path),
+        None => Ok(Resolver::new(config)),
+        Some(path) => Resolver::read(path, config),
     }
 }
 
 /// Represents the dump file contents.
-#[derive(Default, Debug)]
+#[derive(Clone, Default, Debug)]
 pub struct Resolver {
     not_default: bool,
     functions: Vec<String>,
@@ -23,37 +31,65 @@ pub struct Resolver {
     index_function_by_first_pc: Map<ProgramCounter, Index>,
     unresolved_counter: usize,
     pretty_source: Vec<String>,
+    source_files: Vec<String>,
+    index_source_file_by_name: Map<String, Index>,
+    source_lines: BTreeMap<ProgramCounter, Option<(Index, usize)>>, // sort by pc
+    labels: BTreeMap<ProgramCounter, String>,
+    config: Arc<Config>,
 }
 
 const PREFIX_OF_UNRESOLVED: &str = "function_";
 
 impl Resolver {
-    /// Reads the dump file to collect function names and pretty assembly.
+    /// Creates trivial (without function names) instance of the Resolver
+    /// which names functions by the settings.
+    pub fn new(config: &Config) -> Self {
+        Resolver {
+            config: Arc::new(config.clone()),
+            ..Resolver::default()
+        }
+    }
+
+    /// Reads the dump file (or the ELF file) to collect function names and pretty assembly.
     /// Returns non-trivial (with real function names) instance of the Resolver.
-    fn read(filepath: &Path) -> Result<Self> {
+    /// Names of functions are replaced or simplified by the settings.
+    pub fn read(filepath: &Path, config: &Config) -> Result<Self> {
         if global::verbose() {
             tracing::info!("Reading dump file, creating resolver...")
         }
-        let mut resv = Resolver::default();
-        let reader = filebuf::open(filepath)?;
-        parse_dump_file(reader, &mut resv)?;
+        let mut resv = Resolver::new(config);
+        let mut reader = filebuf::open(filepath)?;
+        if elf::is_elf(reader.fill_buf()?) {
+            let bytes = fs::read(filepath).map_err(|e| Error::OpenFile(e, filepath.into()))?;
+            elf::parse_elf_file(&bytes, &mut resv)?;
+        } else {
+            parse_dump_file(reader, &mut resv)?;
+        }
         resv.not_default = true;
         Ok(resv)
     }
 
+    /// Returns settings of the resolver, which also apply to profiles and traces.
+    pub(crate) fn config(&self) -> &Arc<Config> {
+        &self.config
+    }
+
     /// Checks if resolver was generated from nothing (default) or from the dump file.
     pub fn is_default(&self) -> bool {
         !self.not_default
     }
 
-    /// Takes an address and returns name of corresponding function.
-    pub fn resolve_by_address(&self, address: Address) -> String {
+    /// Takes an address of a function called at line `lc` and returns its name.
+    /// Returns an error if the address is unknown.
+    pub fn resolve_by_address(&self, address: Address, lc: usize) -> Result<String> {
         tracing::debug!("Resolver.resolve(0x{:x})", &address);
-        assert_ne!(address, GROUND_ZERO);
-        let func_index = self.index_function_by_address[&address];
-        let func_name = self.functions[func_index].clone();
+        let func_index = self
+            .index_function_by_address
+            .get(&address)
+            .ok_or(Error::UnknownAddress(address, lc))?;
+        let func_name = self.functions[*func_index].clone();
         tracing::debug!("Resolver.resolve returns {})", &func_name);
-        func_name
+        Ok(func_name)
     }
 
     /// Takes a program counter and returns name of function which begins with it (if any).
@@ -64,7 +100,7 @@ impl Resolver {
 
     /// Takes an address and returns name of corresponding function,
     /// otherwise returns a generated string if can not resolve properly.
-    pub fn update(&mut self, address: Address, first_pc: ProgramCounter) -> String {
+    pub(crate) fn update(&mut self, address: Address, first_pc: ProgramCounter) -> String {
         tracing::debug!("Resolver.update(0x{:x}, {})", &address, &first_pc);
         assert_ne!(address, GROUND_ZERO);
 
@@ -75,10 +111,10 @@ impl Resolver {
                 let func_index = self.index_function_by_first_pc[&first_pc];
                 self.index_function_by_address.insert(address, func_index);
             } else {
-                let unresolved_func_name = format!(
+                let unresolved_func_name = self.config.alias(&format!(
                     "{}{} (0x{:x})",
                     PREFIX_OF_UNRESOLVED, self.unresolved_counter, address
-                );
+                ));
                 self.unresolved_counter += 1;
                 let func_index = self.update_first_pc_index(&unresolved_func_name, first_pc);
                 self.index_function_by_address.insert(address, func_index);
@@ -91,6 +127,20 @@ impl Resolver {
         func_name
     }
 
+    /// Takes the hash of a syscall (used as its address) and returns the syscall name.
+    pub(crate) fn update_syscall(&mut self, address: Address, name: &str) -> String {
+        let func_index = match self.index_function_by_address.get(&address) {
+            Some(func_index) => *func_index,
+            None => {
+                self.functions.push(self.config.alias(name));
+                let func_index = self.functions.len() - 1;
+                self.index_function_by_address.insert(address, func_index);
+                func_index
+            }
+        };
+        self.functions[func_index].clone()
+    }
+
     /// Writes source lines from dump file (if any) into the output.
     pub fn write_pretty_source(&self, mut output: impl Write) -> Result<()> {
         writeln!(
@@ -113,6 +163,47 @@ impl Resolver {
         Ok(())
     }
 
+    /// Returns names of functions of the dump (or the ELF) file sorted by their first pcs.
+    pub fn functions(&self) -> Vec<(ProgramCounter, &str)> {
+        let mut functions = self
+            .index_function_by_first_pc
+            .iter()
+            .map(|(pc, i)| (*pc, self.functions[*i].as_str()))
+            .collect::<Vec<_>>();
+        functions.sort_unstable();
+        functions
+    }
+
+    /// Returns pcs of all instructions of the listing.
+    pub fn instructions(&self) -> impl Iterator<Item = ProgramCounter> + '_ {
+        self.pretty_source
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| !s.is_empty())
+            .map(|(pc, _)| pc)
+    }
+
+    /// Returns labels of basic blocks (`LBB...`) by their first pcs.
+    pub fn labels(&self) -> &BTreeMap<ProgramCounter, String> {
+        &self.labels
+    }
+
+    /// Returns the pc following the last instruction of the listing.
+    pub fn end_pc(&self) -> ProgramCounter {
+        self.pretty_source.len()
+    }
+
+    /// Checks if the line table has been read from debug info of the ELF file.
+    pub fn has_source_lines(&self) -> bool {
+        !self.source_lines.is_empty()
+    }
+
+    /// Takes a program counter and returns the source file and line of it (if known).
+    pub fn source_line(&self, pc: ProgramCounter) -> Option<(&str, usize)> {
+        let (_, line) = self.source_lines.range(..=pc).next_back()?;
+        line.map(|(file_index, line)| (self.source_files[file_index].as_str(), line))
+    }
+
     /// Searches a function by name.
     fn contains_function(&self, name: &str) -> bool {
         self.functions.iter().any(|f| f == name)
@@ -123,6 +214,22 @@ impl Resolver {
         self.index_function_by_first_pc.contains_key(&first_pc)
     }
 
+    /// Indexes a function of the dump (or the ELF) file by its first pc.
+    /// The name is replaced with its alias from the config file (if any).
+    /// There can be several copies of identical function,
+    /// so copies get unique names with their addresses appended.
+    fn add_function(&mut self, symbol: &str, first_pc: ProgramCounter) {
+        if self.contains_function_with_first_pc(first_pc) {
+            return;
+        }
+        let simplify = self.config.simplify_names;
+        let mut name = self.config.alias(&names::function_name(symbol, simplify));
+        if self.contains_function(&name) {
+            name = format!("{}@{:#x}", name, first_pc as u64 * INSTRUCTION_SIZE);
+        }
+        self.update_first_pc_index(&name, first_pc);
+    }
+
     /// Creates new entry in the index of functions by their first instruction's pc.
     fn update_first_pc_index(&mut self, name: &str, first_pc: ProgramCounter) -> Index {
         let func_index = self.functions.len();
@@ -131,6 +238,11 @@ impl Resolver {
         func_index
     }
 
+    /// Adds the label of a basic block which starts at the pc.
+    fn add_label(&mut self, pc: ProgramCounter, label: &str) {
+        self.labels.insert(pc, label.into());
+    }
+
     /// Adds new line to the pretty source listing.
     fn add_pretty_source(&mut self, i: usize, s: String) {
         if i >= self.pretty_source.len() {
@@ -139,9 +251,21 @@ impl Resolver {
         self.pretty_source[i] = s;
     }
 
+    /// Returns index of the source file, adding it to the list if needed.
+    fn source_file_index(&mut self, name: &str) -> Index {
+        if let Some(index) = self.index_source_file_by_name.get(name) {
+            return *index;
+        }
+        let index = self.source_files.len();
+        self.source_files.push(name.into());
+        self.index_source_file_by_name.insert(name.into(), index);
+        index
+    }
+
     fn compress(&mut self) {
         self.functions.shrink_to_fit();
         self.pretty_source.shrink_to_fit();
+        self.source_files.shrink_to_fit();
     }
 }
 
@@ -199,27 +323,28 @@ fn parse_dump_file(mut reader: impl BufRead, resv: &mut Resolver) -> Result<()>
         }
 
         if let Some(caps) = LBB.captures(&line) {
-            assert!(label.is_empty());
+            if !label.is_empty() {
+                return Err(Error::DumpParsing(line, lc));
+            }
             label = caps[1].to_string();
         } else if let Some(caps) = FUNC_HEADER.captures(&line) {
-            assert!(function.is_empty());
+            if !function.is_empty() {
+                return Err(Error::DumpParsing(line, lc));
+            }
             function = caps[1].to_string();
         } else if let Some(caps) = INSTRUCTION.captures(&line) {
-            let pc = caps[1]
-                .parse::<ProgramCounter>()
-                .expect("Cannot parse program counter");
+            let pc = match caps[1].parse::<ProgramCounter>() {
+                Ok(pc) => pc,
+                Err(_) => return Err(Error::DumpParsing(line, lc)),
+            };
             let text = caps[3].to_string();
             if !function.is_empty() {
-                if !resv.contains_function_with_first_pc(pc) {
-                    // There can be several copies of identical function,
-                    // so we generate unique name for each copy
-                    while resv.contains_function(&function) {
-                        function += "@";
-                    }
-                    resv.update_first_pc_index(&function, pc);
-                }
+                resv.add_function(&function, pc);
                 function.clear();
             }
+            if !label.is_empty() {
+                resv.add_label(pc, &label);
+            }
             resv.add_pretty_source(
                 pc,
                 if label.is_empty() {
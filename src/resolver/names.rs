@@ -0,0 +1,56 @@
+//! bpf-profile resolver names module.
+//! Implements demangling and simplification of names of functions.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+/// Returns readable name of a function given its symbol.
+/// Mangled Rust symbols are demangled, other names are kept as they are.
+/// If `simplify` is set, removes hashes and generic parameters, so that
+/// `<alloc::vec::Vec<T,A> as core::ops::drop::Drop>::drop::h0123456789abcdef`
+/// becomes `<alloc::vec::Vec as core::ops::drop::Drop>::drop`.
+pub fn function_name(symbol: &str, simplify: bool) -> String {
+    let name = match rustc_demangle::try_demangle(symbol) {
+        Ok(demangled) => format!("{}", demangled),
+        Err(_) => symbol.to_string(),
+    };
+    if simplify {
+        strip_generics(&strip_hash(&name))
+    } else {
+        name
+    }
+}
+
+/// Removes the legacy hash suffix (`::h` and 16 hex digits) of a demangled name.
+pub fn strip_hash(name: &str) -> String {
+    lazy_static! {
+        static ref HASH: Regex = Regex::new(r"::h[[:xdigit:]]{16}$").expect("Invalid regex");
+    }
+    HASH.replace(name, "").into_owned()
+}
+
+/// Removes generic parameters (`Vec<T,A>`, `size_of::<T>`) of a demangled name.
+/// Keeps qualified paths like `<T as Trait>`, removing parameters inside them.
+pub fn strip_generics(name: &str) -> String {
+    let mut result = String::with_capacity(name.len());
+    let mut depth = 0_usize; // of the generic parameters being removed
+    let mut prev = None;
+    let is_path = |p: Option<char>| p.is_some_and(|p| p.is_alphanumeric() || p == '_' || p == ':');
+    for c in name.chars() {
+        match c {
+            '<' if depth > 0 => depth += 1,
+            '<' if is_path(prev) => {
+                depth = 1;
+                if result.ends_with("::") {
+                    result.truncate(result.len() - 2);
+                }
+            }
+            // `->` of function types does not close parameters
+            '>' if depth > 0 && prev != Some('-') => depth -= 1,
+            _ if depth > 0 => {}
+            _ => result.push(c),
+        }
+        prev = Some(c);
+    }
+    result
+}
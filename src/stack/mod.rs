@@ -0,0 +1,222 @@
+//! bpf-profile stack command implementation.
+
+use crate::bpf::{ENTRYPOINT, MAX_CALL_DEPTH, MM_STACK_START, STACK_FRAME_SIZE};
+use crate::config::Config;
+use crate::error::Result;
+use crate::resolver::Resolver;
+use crate::trace::{for_each_section, Step, Walker};
+use crate::{filebuf, global};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+/// Percentage of a limit which is considered close to it.
+const WARNING_PERCENT: u64 = 75;
+
+/// Reads the trace and prints usage of stack frames by functions.
+pub fn run(
+    trace_path: &Path,
+    dump_path: Option<&Path>,
+    config: &Config,
+    lenient: bool,
+) -> Result<()> {
+    let mut resv = crate::resolver::read(dump_path, config)?;
+
+    let mut usage = StackUsage::default();
+    for_each_section(filebuf::open_input(trace_path)?, |section| {
+        usage.trace(section, &mut resv, lenient)
+    })?;
+
+    usage.write(io::stdout())
+}
+
+/// Represents the observed usage of the stack by a function.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameTotals {
+    /// Maximum number of bytes used in the stack frame.
+    pub frame: u64,
+    /// Maximum number of bytes used in all frames of the call stack
+    /// while the function runs.
+    pub stack: u64,
+    /// Maximum depth of calls (the entrypoint is at depth 1).
+    pub depth: usize,
+    /// Number of calls.
+    pub calls: usize,
+}
+
+/// Represents a frame of the call stack being traced.
+#[derive(Debug)]
+struct Frame {
+    name: String,
+    pointer: u64,
+    used: u64,
+}
+
+/// Represents usage of the stack by functions of traced invocations.
+#[derive(Debug, Default)]
+pub struct StackUsage {
+    /// Usage of the stack by names of functions.
+    pub functions: BTreeMap<String, FrameTotals>,
+    /// Names of functions and their used bytes in the call chain
+    /// which used the most of the stack.
+    pub deepest: Vec<(String, u64)>,
+    frames: Vec<Frame>,
+    frame_size: u64,
+}
+
+impl StackUsage {
+    /// Parses a section of the trace line by line, following frames of calls.
+    /// Memory accesses are attributed to the frame which contains the address.
+    pub fn trace(
+        &mut self,
+        reader: impl BufRead,
+        resv: &mut Resolver,
+        lenient: bool,
+    ) -> Result<()> {
+        if global::verbose() {
+            tracing::info!("Parsing trace, collecting stack frames...")
+        }
+
+        for step in Walker::new(reader, resv.config().clone(), lenient) {
+            match step? {
+                Step::Instruction { ix, .. } => {
+                    if self.frames.is_empty() {
+                        let name = resv
+                            .resolve_by_first_pc(ix.pc())
+                            .unwrap_or_else(|| ENTRYPOINT.into());
+                        self.enter_invocation(name, ix.register(10));
+                    }
+                    if let Some((_, address, _)) = ix.memory_access() {
+                        self.access(address);
+                    }
+                }
+                Step::Call { address, first, .. } => {
+                    let name = resv.update(address, first.pc());
+                    self.frames.push(Frame {
+                        name,
+                        pointer: first.register(10),
+                        used: 0,
+                    });
+                }
+                // Syscalls run without frames of the program
+                Step::Syscall { .. } => {}
+                Step::Exit { .. } => self.pop_frame(),
+            }
+        }
+
+        // The final exit of the entrypoint may be missing in truncated traces
+        while !self.frames.is_empty() {
+            self.pop_frame();
+        }
+        Ok(())
+    }
+
+    /// Writes the report: the deepest call chain and the table of functions
+    /// sorted by sizes of their frames. Flags functions close to the limits.
+    pub fn write(&self, mut output: impl Write) -> Result<()> {
+        let frame_warning = STACK_FRAME_SIZE * WARNING_PERCENT / 100;
+        let depth_warning = MAX_CALL_DEPTH as u64 * WARNING_PERCENT / 100;
+
+        writeln!(
+            output,
+            "Limits: {} bytes per frame, {} frames",
+            STACK_FRAME_SIZE, MAX_CALL_DEPTH
+        )?;
+        writeln!(
+            output,
+            "Deepest call chain: {} bytes in {} frames",
+            self.deepest.iter().map(|(_, used)| used).sum::<u64>(),
+            self.deepest.len()
+        )?;
+        writeln!(output, "{:>10} {:>6}  Function", "Frame", "Depth")?;
+        for (depth, (name, used)) in self.deepest.iter().enumerate() {
+            writeln!(output, "{:>10} {:>6}  {}", used, depth + 1, name)?;
+        }
+
+        let mut functions = self.functions.iter().collect::<Vec<_>>();
+        functions.sort_by(|(f1, t1), (f2, t2)| {
+            let key = |t: &FrameTotals| (t.frame, t.stack, t.depth);
+            key(t2).cmp(&key(t1)).then(f1.cmp(f2))
+        });
+
+        writeln!(output)?;
+        writeln!(
+            output,
+            "{:>10} {:>10} {:>6} {:>8}  Function",
+            "Frame", "Stack", "Depth", "Calls"
+        )?;
+        for (name, t) in functions {
+            let mut flags = Vec::new();
+            if t.frame >= frame_warning {
+                flags.push("frame");
+            }
+            if t.depth as u64 >= depth_warning {
+                flags.push("depth");
+            }
+            let flags = if flags.is_empty() {
+                String::default()
+            } else {
+                format!(" (close to the limit: {})", flags.join(", "))
+            };
+            writeln!(
+                output,
+                "{:>10} {:>10} {:>6} {:>8}  {}{}",
+                t.frame, t.stack, t.depth, t.calls, name, flags
+            )?;
+        }
+
+        output.flush()?;
+        Ok(())
+    }
+
+    /// Opens the frame of the entrypoint.
+    /// The frame pointer of the entrypoint tells the size of frames.
+    fn enter_invocation(&mut self, name: String, pointer: u64) {
+        self.frame_size = match pointer.checked_sub(MM_STACK_START) {
+            Some(size) if size > 0 => size,
+            _ => STACK_FRAME_SIZE,
+        };
+        self.frames.push(Frame {
+            name,
+            pointer,
+            used: 0,
+        });
+    }
+
+    /// Updates the used size of the frame which contains the address (if any).
+    fn access(&mut self, address: u64) {
+        let frame_size = self.frame_size;
+        let frame = self
+            .frames
+            .iter_mut()
+            .rev()
+            .find(|f| f.pointer.saturating_sub(frame_size) <= address && address < f.pointer);
+        if let Some(frame) = frame {
+            frame.used = std::cmp::max(frame.used, frame.pointer - address);
+        }
+    }
+
+    /// Closes the current frame adding its usage to the totals of the function.
+    fn pop_frame(&mut self) {
+        let stack = self.frames.iter().map(|f| f.used).sum::<u64>();
+        let depth = self.frames.len();
+        let deepest = self.deepest.iter().map(|(_, used)| used).sum::<u64>();
+        if (stack, depth) > (deepest, self.deepest.len()) {
+            self.deepest = self
+                .frames
+                .iter()
+                .map(|f| (f.name.clone(), f.used))
+                .collect();
+        }
+
+        let frame = match self.frames.pop() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let totals = self.functions.entry(frame.name).or_default();
+        totals.frame = std::cmp::max(totals.frame, frame.used);
+        totals.stack = std::cmp::max(totals.stack, stack);
+        totals.depth = std::cmp::max(totals.depth, depth);
+        totals.calls += 1;
+    }
+}
@@ -1,19 +1,1026 @@
 //! bpf-profile tests module.
 
 use std::io::Cursor;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// Represents a file of a test, removed when the test ends.
+pub(crate) struct TempPath(PathBuf);
+
+impl TempPath {
+    /// Creates new instance of TempPath for the file path.
+    pub(crate) fn new(path: impl Into<PathBuf>) -> Self {
+        TempPath(path.into())
+    }
+}
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Returns a path in the temporary directory unique for the test process.
+pub(crate) fn temp_path(name: &str) -> TempPath {
+    TempPath::new(std::env::temp_dir().join(format!("bpf-profile-{}-{}", std::process::id(), name)))
+}
 
 #[test]
 fn header_missing() {
     let reader = Cursor::new(b"Lorem ipsum dolor sit amet");
-    let r = crate::trace::contains_standard_header(reader);
+    let mut sections = crate::trace::Sections::new(reader);
+    let r = sections.next_section().map(|s| s.is_some());
     assert!(r.is_ok());
     assert!(!r.unwrap());
+    assert_eq!(sections.count(), 0);
 }
 
 #[test]
 fn header_ok() {
     let reader = Cursor::new(b"[Z TRACE bpf] BPF Program Instruction Trace:");
-    let r = crate::trace::contains_standard_header(reader);
+    let mut sections = crate::trace::Sections::new(reader);
+    let r = sections.next_section().map(|s| s.is_some());
     assert!(r.is_ok());
     assert!(r.unwrap());
+    assert_eq!(sections.count(), 1);
+}
+
+#[test]
+fn header_single_section() {
+    let reader = Cursor::new(b"[Z TRACE bpf] BPF Program Instruction Trace:");
+    let mut sections = crate::trace::Sections::new(reader);
+    assert!(sections.next_section().unwrap().is_some());
+    assert!(sections.next_section().unwrap().is_none());
+    assert_eq!(sections.count(), 1);
+}
+
+#[test]
+fn sections() {
+    use std::io::BufRead;
+    let reader = Cursor::new(
+        b"Lorem ipsum
+[Z TRACE bpf] BPF Program Instruction Trace:
+ 1 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 1: aaa
+ 2 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 2: exit
+[Z TRACE bpf] BPF Program Instruction Trace:
+[Z TRACE bpf] BPF Program Instruction Trace:
+ 1 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 1: bbb",
+    );
+    let mut sections = crate::trace::Sections::new(reader);
+    let mut lines = Vec::new();
+    while let Some(section) = sections.next_section().unwrap() {
+        let number = section.number();
+        for line in section.lines() {
+            lines.push((number, line.unwrap()));
+        }
+    }
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0].0, 1);
+    assert!(lines[1].1.ends_with("2: exit"));
+    assert_eq!(lines[2].0, 3);
+    assert!(lines[2].1.ends_with("1: bbb"));
+}
+
+#[test]
+fn sections_interleaved() {
+    use std::io::BufRead;
+    let reader = Cursor::new(
+        b"[2021-05-14T18:59:13.635467254Z TRACE solana_bpf_loader_program solana-banking-1] BPF Program Instruction Trace:
+ 1 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 1: aaa
+[2021-05-14T18:59:13.636467254Z TRACE solana_bpf_loader_program solana-banking-2] BPF Program Instruction Trace:
+ 1 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 1: bbb
+[2021-05-14T18:59:13.637467254Z INFO  solana_metrics::metrics solana-metrics] datapoint: bank-timestamp
+[2021-05-14T18:59:13.638467254Z TRACE solana_bpf_loader_program solana-banking-1]  2 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 2: exit
+[2021-05-14T18:59:13.639467254Z TRACE solana_bpf_loader_program solana-banking-2] BPF Program Instruction Trace:
+ 1 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 1: ccc
+[2021-05-14T18:59:13.640467254Z TRACE solana_bpf_loader_program solana-banking-1]  3 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 3: ddd",
+    );
+    let mut sections = crate::trace::Sections::new(reader);
+    let mut lines = Vec::new();
+    while let Some(section) = sections.next_section().unwrap() {
+        let number = section.number();
+        for line in section.lines() {
+            lines.push((number, line.unwrap()));
+        }
+    }
+    let expected = [
+        (1, "1: aaa"),
+        (1, "2: exit"),
+        (1, "3: ddd"),
+        (2, "1: bbb"),
+        (3, "1: ccc"),
+    ];
+    assert_eq!(lines.len(), expected.len());
+    for ((number, line), (n, suffix)) in lines.iter().zip(expected) {
+        assert_eq!(number, &n);
+        assert!(line.starts_with(' ') && line.ends_with(suffix), "{}", line);
+    }
+}
+
+#[test]
+fn sections_without_thread() {
+    use std::io::BufRead;
+    let reader = Cursor::new(
+        b"[2021-05-14T18:59:13.635467254Z TRACE solana_bpf_loader_program] BPF Program Instruction Trace:
+[2021-05-14T18:59:13.635467254Z TRACE solana_bpf_loader_program]  1 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 1: aaa
+[2021-05-14T18:59:13.636467254Z INFO  solana_metrics::metrics] datapoint: bank-timestamp
+[2021-05-14T18:59:13.637467254Z TRACE solana_bpf_loader_program] BPF Program Instruction Trace:
+[2021-05-14T18:59:13.638467254Z TRACE solana_bpf_loader_program]  1 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 1: bbb",
+    );
+    let mut sections = crate::trace::Sections::new(reader);
+    let mut lines = Vec::new();
+    while let Some(section) = sections.next_section().unwrap() {
+        let number = section.number();
+        for line in section.lines() {
+            lines.push((number, line.unwrap()));
+        }
+    }
+    let expected = [(1, "1: aaa"), (1, "bank-timestamp"), (2, "1: bbb")];
+    assert_eq!(lines.len(), expected.len());
+    for ((number, line), (n, suffix)) in lines.iter().zip(expected) {
+        assert_eq!(number, &n);
+        assert!(line.ends_with(suffix), "{}", line);
+    }
+}
+
+#[test]
+fn sections_interleaved_without_thread() {
+    use crate::error::Error;
+    use crate::{Profile, Resolver};
+    // The default log format of Solana, the second trace starts within the first one
+    let trace = b"[2021-05-14T18:59:13.635382365Z TRACE solana_bpf_loader_program] BPF Program Instruction Trace:
+    0 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 0: mov64 r1, 0x1
+    1 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 1: call 0x5
+[2021-05-14T18:59:13.635467254Z TRACE solana_bpf_loader_program] BPF Program Instruction Trace:
+    0 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 0: mov64 r1, 0x1
+    2 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 7: add64 r1, 0x1
+    3 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 8: exit
+    1 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 1: exit
+    4 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 2: exit
+";
+    let r = Profile::new(Resolver::default(), None)
+        .unwrap()
+        .read(Cursor::new(trace));
+    assert!(matches!(r, Err(Error::TraceInterleaved(ix, 2)) if ix.ends_with("7: add64 r1, 0x1")));
+
+    let mut profile = Profile::new(Resolver::default(), None).unwrap();
+    profile.set_lenient(true);
+    assert!(profile.read(Cursor::new(trace)).is_ok());
+}
+
+#[test]
+fn sections_pending_limit() {
+    use std::io::BufRead;
+    let reader = Cursor::new(
+        b"[Z TRACE bpf tx-1] BPF Program Instruction Trace:
+[Z TRACE bpf tx-2] BPF Program Instruction Trace:
+[Z TRACE bpf tx-2]  1 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 1: aaa
+[Z TRACE bpf tx-2]  2 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 2: bbb
+[Z TRACE bpf tx-1]  1 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 1: ccc",
+    );
+    let mut sections = crate::trace::Sections::with_pending_limit(reader, 2);
+    let section = sections.next_section().unwrap().unwrap();
+    let r = section.lines().collect::<std::io::Result<Vec<_>>>();
+    assert!(r.is_err());
+}
+
+/// Reads one byte at a time, as pipes may do.
+struct ByteReader<R>(R);
+
+impl<R: std::io::Read> std::io::Read for ByteReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(1);
+        self.0.read(&mut buf[..len])
+    }
+}
+
+const CALL_TRACE: &[u8] = b"[Z TRACE bpf] BPF Program Instruction Trace:
+ 0 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 0: mov64 r1, 0x1
+ 1 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 1: call 0x5
+ 2 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 7: add64 r1, 0x1
+ 3 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 8: exit
+ 4 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 2: exit
+";
+
+/// Reads the trace by `open_input` and returns its folded stacks.
+fn read_input(path: &Path, stdin: impl std::io::Read + 'static) -> String {
+    use crate::{Profile, Resolver};
+    let input = crate::filebuf::open_input_with(path, stdin).unwrap();
+    let mut profile = Profile::new(Resolver::default(), None).unwrap();
+    profile.read(input).unwrap();
+    let mut output = Vec::<u8>::new();
+    profile.write_folded(&mut output).unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn input_compressed() {
+    use std::io::{Read, Write};
+    let plain = read_input(Path::new("-"), Cursor::new(CALL_TRACE));
+    assert_eq!(plain, "entrypoint 3\nentrypoint;function_0 (0x5) 2\n");
+
+    let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gzip.write_all(CALL_TRACE).unwrap();
+    let gzip = gzip.finish().unwrap();
+    let zstd = zstd::encode_all(CALL_TRACE, 0).unwrap();
+    for (name, compressed) in [("trace.gz", gzip), ("trace.zst", zstd)] {
+        let stdin = ByteReader(Cursor::new(compressed.clone()));
+        assert_eq!(read_input(Path::new("-"), stdin), plain, "{}", name);
+
+        let path = temp_path(name);
+        std::fs::write(&path, &compressed).unwrap();
+        assert_eq!(read_input(&path, std::io::empty()), plain, "{}", name);
+    }
+
+    let stdin = ByteReader(Cursor::new(b"[Z"));
+    let mut input = crate::filebuf::open_input_with(Path::new("-"), stdin).unwrap();
+    let mut short = String::new();
+    input.read_to_string(&mut short).unwrap();
+    assert_eq!(short, "[Z");
+}
+
+#[test]
+fn profile_read() {
+    use crate::{Profile, Resolver};
+    let trace = b"[Z TRACE bpf] BPF Program Instruction Trace:
+ 0 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 0: mov64 r1, 0x1
+ 1 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 1: call 0x5
+ 2 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 7: add64 r1, 0x1
+ 3 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 8: exit
+ 4 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 2: exit
+";
+    let mut profile = Profile::new(Resolver::default(), None).unwrap();
+    profile.read(Cursor::new(trace)).unwrap();
+    let mut output = Vec::<u8>::new();
+    profile.write_folded(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "entrypoint 3\nentrypoint;function_0 (0x5) 2\n"
+    );
+    let summary = profile.summary();
+    assert_eq!(summary.total_cost, 5);
+    let names = summary.functions.keys().collect::<Vec<_>>();
+    assert_eq!(names, ["entrypoint", "function_0 (0x5)"]);
+    assert_eq!(summary.functions["entrypoint"].inclusive_cost, 5);
+
+    let r = Profile::new(Resolver::default(), None)
+        .unwrap()
+        .read(Cursor::new(b"Lorem ipsum"));
+    assert!(matches!(r, Err(crate::error::Error::TraceFormat)));
+}
+
+#[test]
+fn lenient() {
+    use crate::error::Error;
+    use crate::{Profile, Resolver};
+    let regs = "[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]";
+    let bad_target = format!("1 {0} 1: call zz\n2 {0} 7: exit\n", regs);
+    let inconsistent = format!("1 {0} 1: mov64 r1, 0x1\n2 {0} 1: mov64 r1, 0x2\n", regs);
+    let pc_zero = format!("1 {0} 0: mov64 r1, 0x1\n", regs);
+    let pc_huge = format!("1 {0} 99999999999: mov64 r1, 0x1\n", regs);
+    // The callee is replaced by corrupted bytes
+    let no_callee = [
+        format!("1 {0} 1: mov64 r1, 0x1\n2 {0} 2: call 0x5\n", regs).as_bytes(),
+        b"\xff\xfe\n",
+    ]
+    .concat();
+    let read = |bodies: &[&[u8]], lenient| {
+        let asm = &temp_path("lenient.asm");
+        let mut profile = Profile::new(Resolver::default(), Some(asm.as_ref())).unwrap();
+        profile.set_lenient(lenient);
+        let mut trace = b"[Z TRACE bpf] BPF Program Instruction Trace:\n".to_vec();
+        trace.extend(bodies.concat());
+        profile.read(Cursor::new(trace)).map(|_| profile)
+    };
+
+    let r = read(&[bad_target.as_bytes()], false);
+    assert!(matches!(r, Err(Error::TraceParsing(ix, 1)) if ix == "call zz"));
+    let r = read(&[inconsistent.as_bytes()], false);
+    assert!(matches!(r, Err(Error::TraceInconsistent(_, ix, 2)) if ix.ends_with("0x2")));
+    let r = read(&[&no_callee], false);
+    assert!(matches!(r, Err(Error::TraceNoCallee(ix, 2)) if ix == "call 0x5"));
+    let r = read(&[pc_zero.as_bytes()], false);
+    assert!(matches!(r, Err(Error::TraceParsing(_, 1))));
+    let r = read(&[pc_huge.as_bytes()], false);
+    assert!(matches!(r, Err(Error::TraceParsing(_, 1))));
+
+    let bodies = [
+        bad_target.as_bytes(),
+        inconsistent.as_bytes(),
+        &no_callee,
+        pc_zero.as_bytes(),
+        pc_huge.as_bytes(),
+    ];
+    let r = read(&bodies, true);
+    assert_eq!(r.unwrap().summary().total_cost, 8);
+}
+
+#[test]
+fn calls_json() {
+    use crate::calls::{trace_calls, write_json};
+    use crate::resolver::Resolver;
+    let trace = b"0 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 1: mov64 r1, 0x1
+1 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 2: call 0x10
+2 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 9: call 0x20
+3 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 20: exit
+4 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 10: exit
+5 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 3: exit
+";
+    let mut resv = Resolver::default();
+    let invocation = trace_calls(Cursor::new(trace), &mut resv, false).unwrap();
+    assert_eq!(invocation.1, 6);
+
+    let mut output = Vec::<u8>::new();
+    write_json(&mut output, &[(1, invocation)], &resv).unwrap();
+    let document: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(document[0]["invocation"], 1);
+    assert_eq!(document[0]["instructions"], 6);
+    let call = &document[0]["calls"][0];
+    assert_eq!(call["name"], "function_0 (0x10)");
+    assert_eq!(call["address"], 0x10);
+    assert_eq!(call["caller_pc"], 2);
+    assert_eq!(call["lines"], serde_json::json!([2, 5]));
+    assert_eq!(call["self_instructions"], 2);
+    assert_eq!(call["inclusive_instructions"], 3);
+    let callee = &call["calls"][0];
+    assert_eq!(callee["name"], "function_1 (0x20)");
+    assert_eq!(callee["caller_pc"], 9);
+    assert_eq!(callee["lines"], serde_json::json!([3, 4]));
+    assert_eq!(callee["inclusive_instructions"], 1);
+    assert_eq!(callee["calls"], serde_json::json!([]));
+}
+
+#[test]
+fn stack_usage() {
+    use crate::resolver::Resolver;
+    use crate::stack::{FrameTotals, StackUsage};
+    let regs = |r1: u64, r10: u64| format!("[0, {:x}, 0, 0, 0, 0, 0, 0, 0, 0, {:x}]", r1, r10);
+    let trace = [
+        format!("0 {} 1: stxdw [r10+0xfff0], r1", regs(0, 0x200001000)),
+        format!("1 {} 2: call 0x10", regs(0, 0x200001000)),
+        format!("2 {} 9: stxdw [r10+0xf400], r1", regs(0, 0x200003000)),
+        // Writes into the frame of the caller via a pointer
+        format!(
+            "3 {} 10: stxb [r1-0x20], r1",
+            regs(0x200001000, 0x200003000)
+        ),
+        format!("4 {} 11: exit", regs(0x200001000, 0x200003000)),
+        format!("5 {} 3: exit", regs(0, 0x200001000)),
+    ]
+    .join("\n");
+    let mut usage = StackUsage::default();
+    usage
+        .trace(Cursor::new(trace), &mut Resolver::default(), false)
+        .unwrap();
+    assert_eq!(
+        usage.functions["entrypoint"],
+        FrameTotals {
+            frame: 32,
+            stack: 32,
+            depth: 1,
+            calls: 1
+        }
+    );
+    let callee = &usage.deepest[1].0;
+    assert_eq!(
+        usage.functions[callee],
+        FrameTotals {
+            frame: 3072,
+            stack: 3104,
+            depth: 2,
+            calls: 1
+        }
+    );
+
+    let mut output = Vec::<u8>::new();
+    usage.write(&mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("Deepest call chain: 3104 bytes in 2 frames\n"));
+    assert!(output.contains(
+        "      3072       3104      2        1  function_0 (0x10) (close to the limit: frame)\n"
+    ));
+}
+
+#[test]
+fn memory_regions() {
+    use crate::bpf::Region;
+    use crate::memory::MemoryUsage;
+    use crate::resolver::Resolver;
+    assert_eq!(Region::of(0x1_0000_0120), Some(Region::Program));
+    assert_eq!(Region::of(0x2_0000_0ff8), Some(Region::Stack));
+    assert_eq!(Region::of(0x3_0000_0000), Some(Region::Heap));
+    assert_eq!(Region::of(0x4_ffff_ffff), Some(Region::Input));
+    assert_eq!(Region::of(0x10), None);
+    assert_eq!(Region::of(0x5_0000_0000), None);
+
+    let regs = |r1: u64, r10: u64| format!("[0, {:x}, 0, 0, 0, 0, 0, 0, 0, 0, {:x}]", r1, r10);
+    let trace = [
+        format!("0 {} 1: ldxdw r2, [r1+0x8]", regs(0x400000000, 0x200001000)),
+        format!("1 {} 2: call 0x10", regs(0x300000000, 0x200001000)),
+        format!("2 {} 9: stxw [r10-0x4], r1", regs(0x300000000, 0x200002000)),
+        format!("3 {} 10: stb [r1+0x0], 0x1", regs(0x300000000, 0x200002000)),
+        format!("4 {} 11: ldxh r2, [r1+0x0]", regs(0x10, 0x200002000)),
+        format!("5 {} 12: exit", regs(0, 0x200002000)),
+        format!("6 {} 3: stxdw [r10-0x8], r2", regs(0, 0x200001000)),
+        format!("7 {} 4: exit", regs(0, 0x200001000)),
+    ]
+    .join("\n");
+    let mut usage = MemoryUsage::default();
+    usage
+        .trace(Cursor::new(trace), &mut Resolver::default(), false)
+        .unwrap();
+    let entry = &usage.functions["entrypoint"];
+    assert_eq!(entry.read, [0, 0, 0, 8]);
+    assert_eq!(entry.written, [0, 8, 0, 0]);
+    let callee = &usage.functions["function_0 (0x10)"];
+    assert_eq!(callee.read, [0, 0, 0, 0]);
+    assert_eq!(callee.written, [0, 4, 1, 0]);
+    assert_eq!(usage.unknown, 1);
+
+    let mut output = Vec::<u8>::new();
+    usage.write(&mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains(
+        "          0         0          0        12          0         1          8         0  Total\n"
+    ));
+}
+
+#[test]
+fn instruction_registers() {
+    let line = "      570 [0000000300007F74, 0000000200004FC8, 000000010000F348, 000000000000000A, 0000000100004950, 0000000000000001, 0000000200002FE8, 0000000200002F98, 0000000000000001, 0000000000000000, 0000000200007000]  4486: callx 0x4";
+    let ix = crate::bpf::Instruction::parse(line, 1).unwrap();
+    assert_eq!(ix.pc(), 4486);
+    assert_eq!(ix.text(), "callx 0x4");
+    assert_eq!(ix.register(0), 0x300007F74);
+    assert_eq!(ix.register(4), 0x100004950);
+    assert_eq!(ix.registers()[10], 0x200007000);
+
+    let ix =
+        crate::bpf::Instruction::parse(" 1 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1] 1: aaa", 1).unwrap();
+    assert_eq!(ix.register(10), 1);
+
+    let r = crate::bpf::Instruction::parse(" 1 [0, 0, 0] 1: aaa", 1);
+    assert!(matches!(r, Err(crate::error::Error::TraceSkipped)));
+}
+
+#[test]
+fn indirect_call_target() {
+    let call = "      570 [0000000300007F74, 0000000200004FC8, 000000010000F348, 000000000000000A, 0000000100004950, 0000000000000001, 0000000200002FE8, 0000000200002F98, 0000000000000001, 0000000000000000, 0000000200007000]  4486: callx 0x4";
+    let first = "      571 [0000000300007F74, 0000000200004FC8, 000000010000F348, 000000000000000A, 0000000100004950, 0000000000000001, 0000000200002FE8, 0000000200002F98, 0000000000000001, 0000000000000000, 0000000200009000]  2346: mov64 r6, r3";
+    let call = crate::bpf::Instruction::parse(call, 1).unwrap();
+    let first = crate::bpf::Instruction::parse(first, 2).unwrap();
+    assert!(call.is_indirect_call());
+    assert_eq!(call.call_target(&first, 1).unwrap(), 0x100004950);
+
+    // Falls back to the pc of the callee without register values
+    let call = crate::bpf::Instruction::new(3, "callx 0x4".into());
+    assert_eq!(call.call_target(&first, 1).unwrap(), 0x100004950);
+
+    let call = crate::bpf::Instruction::new(3, "callx 0xb".into());
+    assert!(call.call_target(&first, 1).is_err());
+}
+
+#[test]
+fn instruction_class() {
+    let cases = [
+        ("lddw r1, 0x100000000", "ld", "Loads"),
+        ("ldxdw r6, [r10+0xff58]", "ldx", "Loads"),
+        ("stdw [r10+0xff58], 0x0", "st", "Stores"),
+        ("stxb [r1+0x0], r2", "stx", "Stores"),
+        ("add32 r1, 0x1", "alu", "ALU"),
+        ("be16 r1", "alu", "ALU"),
+        ("mod32 r1, 0x3", "alu", "MulDiv"),
+        ("jeq r0, 0x0, +24", "jmp", "Branches"),
+        ("call 0x207559bd", "jmp", "Calls"),
+        ("exit", "jmp", "Calls"),
+        ("mov64 r0, r1", "alu64", "ALU"),
+        ("mul64 r0, r1", "alu64", "MulDiv"),
+    ];
+    for (text, class, category) in cases {
+        let ix = crate::bpf::Instruction::new(1, text.into());
+        assert_eq!(ix.class(), class, "{}", text);
+        assert_eq!(ix.category(), category, "{}", text);
+    }
+}
+
+#[test]
+fn syscalls() {
+    use crate::bpf::{hash_symbol_name, Instruction};
+    use crate::config::Config;
+    assert_eq!(hash_symbol_name(b""), 0);
+    assert_eq!(hash_symbol_name(b"abort"), 0xb6fc1a11);
+    assert_eq!(hash_symbol_name(b"sol_log_"), 0x207559bd);
+    assert_eq!(hash_symbol_name(b"sol_log_64_"), 0x5c2a3178);
+
+    let registers = "[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]";
+    let ix = Instruction::parse(&format!("1 {} 6552: call 0x207559bd", registers), 1).unwrap();
+    assert_eq!(ix.syscall(&Config::default()).as_deref(), Some("sol_log_"));
+    let ix = Instruction::parse(&format!("2 {} 36: call 0x8e0001f9", registers), 2).unwrap();
+    assert!(ix.syscall(&Config::default()).is_none());
+    let ix = Instruction::parse(&format!("3 {} 37: callx 0x207559bd", registers), 3).unwrap();
+    assert!(ix.syscall(&Config::default()).is_none());
+}
+
+#[test]
+fn function_names() {
+    use crate::resolver::names::function_name;
+    let symbol = "_ZN4core3ptr13drop_in_place17h4b9d6a8c0f2e1a37E";
+    assert_eq!(
+        function_name(symbol, false),
+        "core::ptr::drop_in_place::h4b9d6a8c0f2e1a37"
+    );
+    assert_eq!(function_name(symbol, true), "core::ptr::drop_in_place");
+    let symbol = "_ZN70_$LT$alloc..vec..Vec$LT$T$C$A$GT$$u20$as$u20$core..ops..drop..Drop$GT$4drop17h0123456789abcdefE";
+    assert_eq!(
+        function_name(symbol, false),
+        "<alloc::vec::Vec<T,A> as core::ops::drop::Drop>::drop::h0123456789abcdef"
+    );
+    assert_eq!(
+        function_name(symbol, true),
+        "<alloc::vec::Vec as core::ops::drop::Drop>::drop"
+    );
+    let cases = [
+        ("entrypoint", "entrypoint"),
+        (
+            "core::cell::Cell<T>::replace::h0123456789abcdef",
+            "core::cell::Cell::replace",
+        ),
+        (
+            "<T as core::convert::Into<U>>::into",
+            "<T as core::convert::Into>::into",
+        ),
+        ("<&mut I as Iterator>::next", "<&mut I as Iterator>::next"),
+        ("core::mem::size_of::<u64>", "core::mem::size_of"),
+        ("call<Box<dyn Fn(u8) -> u8>>", "call"),
+    ];
+    for (name, simplified) in cases {
+        assert_eq!(function_name(name, false), name);
+        assert_eq!(function_name(name, true), simplified);
+    }
+}
+
+#[test]
+fn config_file() {
+    let path = &temp_path("test.conf");
+    std::fs::write(
+        path,
+        r#"
+dump = "program.dump"
+asm = "/tmp/program.asm"
+format = "folded"
+simplify_names = true
+ignore = ["^core::fmt::"]
+syscalls = ["sol_custom_"]
+
+[aliases]
+"spl_memo::processor::process_instruction" = "process"
+
+[compute_units.classes]
+ldx = 2
+
+[compute_units.syscalls]
+sol_log_ = 50
+"#,
+    )
+    .unwrap();
+    let config = crate::config::Config::read(Some(path.as_ref())).unwrap();
+    // Relative paths are relative to the directory of the config file
+    let dir = path.parent().unwrap();
+    assert_eq!(config.dump, Some(dir.join("program.dump")));
+    assert_eq!(config.asm, Some(PathBuf::from("/tmp/program.asm")));
+    assert!(config.elf.is_none());
+    assert_eq!(config.format.as_deref(), Some("folded"));
+    assert!(config.tab.is_none());
+    assert!(config.simplify_names);
+    assert_eq!(
+        config.alias("spl_memo::processor::process_instruction"),
+        "process"
+    );
+    assert_eq!(config.alias("memcpy"), "memcpy");
+    assert!(config.is_ignored("core::fmt::write"));
+    assert!(!config.is_ignored("alloc::fmt::format"));
+    let hash = crate::bpf::hash_symbol_name(b"sol_custom_");
+    assert_eq!(config.syscall(hash).as_deref(), Some("sol_custom_"));
+    assert!(config.syscall(hash + 1).is_none());
+    assert_eq!(config.instruction_units("ldx"), 2);
+    assert_eq!(config.instruction_units("alu64"), 1);
+    assert_eq!(config.syscall_units("sol_log_"), 50);
+    assert_eq!(config.syscall_units("sol_sha256"), 85);
+    assert_eq!(config.syscall_units("sol_custom_"), 100);
+
+    std::fs::write(path, "format = \"unknown\"").unwrap();
+    let r = crate::config::Config::read(Some(path.as_ref()));
+    assert!(matches!(r, Err(crate::error::Error::Config(_, _))));
+
+    std::fs::write(path, "[compute_units.classes]\nalu128 = 1").unwrap();
+    let r = crate::config::Config::read(Some(path.as_ref()));
+    assert!(matches!(r, Err(crate::error::Error::Config(_, _))));
+}
+
+#[test]
+fn config_of_resolver() {
+    use crate::config::Config;
+    use crate::{Profile, Resolver};
+    let path = &temp_path("resolver.conf");
+    std::fs::write(path, "[aliases]\n\"function_0 (0x5)\" = \"helper\"\n").unwrap();
+    let config = Config::read(Some(path.as_ref())).unwrap();
+    // Settings apply to profiles of the resolver only
+    for (resv, callee) in [
+        (Resolver::new(&config), "helper"),
+        (Resolver::default(), "function_0 (0x5)"),
+    ] {
+        let mut profile = Profile::new(resv, None).unwrap();
+        profile.read(Cursor::new(CALL_TRACE)).unwrap();
+        let summary = profile.summary();
+        let names = summary.functions.keys().collect::<Vec<_>>();
+        assert_eq!(names, ["entrypoint", callee]);
+    }
+}
+
+#[test]
+fn diff() {
+    use crate::gen::Summary;
+    let mut old = Summary::default();
+    old.add_self_cost("main".into(), 10);
+    old.add_self_cost("helper".into(), 5);
+    old.add_self_cost("gone".into(), 3);
+    old.add_call(None, "main".into(), 18);
+    old.add_call(Some("main".into()), "helper".into(), 5);
+    old.add_call(Some("main".into()), "gone".into(), 3);
+    let mut new = Summary::default();
+    new.add_self_cost("main".into(), 10);
+    new.add_self_cost("helper".into(), 12);
+    new.add_self_cost("fresh".into(), 1);
+    new.add_call(None, "main".into(), 23);
+    new.add_call(Some("main".into()), "helper".into(), 6);
+    new.add_call(Some("main".into()), "helper".into(), 6);
+    new.add_call(Some("main".into()), "fresh".into(), 1);
+
+    let mut output = Vec::<u8>::new();
+    crate::diff::write_table(&mut output, &old, &new).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(
+        output,
+        "Total instructions: 18 -> 23 (+5)
+      Self  Inclusive    Calls  Function
+        +7         +7       +1  helper
+        +0         +5       +0  main
+        -3         -3       -1  gone (removed)
+        +1         +1       +1  fresh (added)
+"
+    );
+
+    let mut output = Vec::<u8>::new();
+    crate::diff::write_callgrind(&mut output, &old, &new).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("events: Increase Decrease\ntotals: 8 3\n"));
+    assert!(output.contains("fn=main\ncfn=fresh\ncalls=1 0\n0 1 0\n"));
+    assert!(output.contains("cfn=gone\ncalls=1 0\n0 0 3\ncfn=helper\ncalls=2 0\n0 7 0\n"));
+}
+
+#[test]
+fn summary_recursion() {
+    use crate::gen::{Profile, Totals};
+    use crate::resolver::Resolver;
+    let trace = b"[Z TRACE bpf] BPF Program Instruction Trace:
+ 0 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 0: mov64 r1, 0x1
+ 1 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 1: call 0x5
+ 2 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 7: add64 r1, 0x1
+ 3 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 8: call 0x5
+ 4 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 7: add64 r1, 0x1
+ 5 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 10: exit
+ 6 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 9: exit
+ 7 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 2: exit
+";
+    let mut profile = Profile::new(Resolver::default(), None).unwrap();
+    profile.read(Cursor::new(trace)).unwrap();
+    let summary = profile.summary();
+    assert_eq!(summary.total_cost, 8);
+    assert_eq!(
+        summary.functions["function_0 (0x5)"],
+        Totals {
+            self_cost: 5,
+            inclusive_cost: 5,
+            calls: 2
+        }
+    );
+}
+
+#[test]
+fn top() {
+    use crate::gen::Summary;
+    let mut summary = Summary::default();
+    summary.add_self_cost("main".into(), 2);
+    summary.add_call(None, "main".into(), 10);
+    summary.add_self_cost("helper".into(), 5);
+    summary.add_call(Some("main".into()), "helper".into(), 3);
+    summary.add_call(Some("main".into()), "helper".into(), 2);
+    summary.add_self_cost("log".into(), 3);
+    summary.add_call(Some("main".into()), "log".into(), 3);
+
+    let mut output = Vec::<u8>::new();
+    crate::top::write_table(&mut output, &summary, 2).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(
+        output,
+        "Total instructions: 10
+      Self   Self%  Inclusive   Incl%    Calls   Per call  Function
+         5  50.00%          5  50.00%        2        2.5  helper
+         3  30.00%          3  30.00%        1        3.0  log
+... 1 more functions
+"
+    );
+}
+
+#[test]
+fn disassemble() {
+    let cases: &[(&[u8], &str)] = &[
+        (&[0x79, 0x16, 0x08, 0, 0, 0, 0, 0], "ldxdw r6, [r1+0x8]"),
+        (
+            &[0x7b, 0x1a, 0xd8, 0xfe, 0, 0, 0, 0],
+            "stxdw [r10+0xfed8], r1",
+        ),
+        (&[0x15, 0x06, 0x06, 0, 0, 0, 0, 0], "jeq r6, 0x0, +6"),
+        (&[0x27, 0x06, 0, 0, 0x30, 0, 0, 0], "mul64 r6, 0x30"),
+        (
+            &[0x07, 0x01, 0, 0, 0xb0, 0xff, 0xff, 0xff],
+            "add64 r1, 0xffffffb0",
+        ),
+        (&[0xbf, 0x62, 0, 0, 0, 0, 0, 0], "mov64 r2, r6"),
+        (&[0x05, 0, 0x18, 0, 0, 0, 0, 0], "ja +0x18"),
+        (&[0x87, 0x02, 0, 0, 0, 0, 0, 0], "neg64 r2"),
+        (&[0x8d, 0, 0, 0, 0x04, 0, 0, 0], "callx 0x4"),
+        (&[0x95, 0, 0, 0, 0, 0, 0, 0], "exit"),
+    ];
+    for (bytes, text) in cases {
+        assert_eq!(crate::bpf::disassemble(bytes), (text.to_string(), 8));
+    }
+    let lddw = [
+        0x18, 0x02, 0, 0, 0x55, 0x55, 0x55, 0x55, 0, 0, 0, 0, 0x55, 0x55, 0x55, 0x55,
+    ];
+    assert_eq!(
+        crate::bpf::disassemble(&lddw),
+        ("lddw r2, 0x5555555555555555".to_string(), 16)
+    );
+}
+
+#[test]
+fn resolver_elf() {
+    let path = &temp_path("test.so");
+    std::fs::write(path, mock_elf(&[])).unwrap();
+    let resv = crate::resolver::read(Some(path.as_ref()), &Default::default()).unwrap();
+    assert!(!resv.is_default());
+    assert_eq!(
+        resv.resolve_by_first_pc(36).unwrap(),
+        "test::entrypoint::h0123456789abcdef"
+    );
+    assert_eq!(resv.resolve_by_first_pc(39).unwrap(), "helper");
+    assert!(resv.resolve_by_first_pc(38).is_none());
+
+    let mut output = Vec::<u8>::new();
+    resv.write_pretty_source(&mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(
+        output.contains("36:        mov64 r0, 0x0        ; test::entrypoint::h0123456789abcdef\n")
+    );
+    assert!(output.contains("37:        call 0x1        ; helper\n"));
+    assert!(output.contains("38:        exit        ; LBB0_1\n"));
+    assert!(output.contains("39:        mov64 r0, r1        ; helper\n"));
+}
+
+#[test]
+fn coverage() {
+    use crate::coverage::Coverage;
+    let path = &temp_path("coverage.so");
+    std::fs::write(path, mock_elf(&[])).unwrap();
+    let resv = crate::resolver::read(Some(path.as_ref()), &Default::default()).unwrap();
+    assert_eq!(resv.labels()[&38], "LBB0_1");
+
+    let trace = b"0 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 36: mov64 r0, 0x0
+1 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 37: call 0x1
+";
+    let mut coverage = Coverage::default();
+    coverage.trace(Cursor::new(trace), &resv, false).unwrap();
+    let functions = coverage.functions(&resv);
+    assert_eq!(functions.len(), 2);
+    assert_eq!(functions[0].name, "test::entrypoint::h0123456789abcdef");
+    assert_eq!(functions[0].blocks.len(), 2);
+    assert_eq!(functions[0].executed(), 1);
+    assert_eq!(functions[1].name, "helper");
+    assert_eq!(functions[1].executed(), 0);
+
+    let mut output = Vec::<u8>::new();
+    coverage.write(&resv, &mut output, true).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(
+        output.starts_with("Functions executed: 1 of 2 (50.0%)\nBlocks executed: 1 of 3 (33.3%)\n")
+    );
+    assert!(output.contains("       2        1   50.0%  test::entrypoint::h0123456789abcdef\n"));
+    assert!(output.contains("  not executed: LBB0_1 (pc 38)\n"));
+    assert!(output.contains("       1        0    0.0%  helper\n"));
+}
+
+#[test]
+fn folded_sections() {
+    use crate::{Profile, Resolver};
+    let path = &temp_path("folded.so");
+    std::fs::write(path, mock_elf(&[])).unwrap();
+    let section = "[2021-05-14T18:59:13.635382365Z TRACE solana_bpf_loader_program] \
+BPF Program Instruction Trace:
+        0 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 36: mov64 r0, 0x0
+        1 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 37: call 0x1
+        2 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 39: mov64 r0, r1
+        3 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 40: exit
+        4 [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] 38: exit
+";
+    let trace = [section, section].concat();
+    let mut profile =
+        Profile::new(Resolver::read(path, &Default::default()).unwrap(), None).unwrap();
+    profile.read(Cursor::new(trace)).unwrap();
+
+    let mut output = Vec::<u8>::new();
+    profile.write_folded(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "test::entrypoint::h0123456789abcdef 6\ntest::entrypoint::h0123456789abcdef;helper 4\n"
+    );
+    let summary = profile.summary();
+    assert_eq!(
+        summary.functions["test::entrypoint::h0123456789abcdef"].calls,
+        2
+    );
+    assert_eq!(
+        summary.functions["test::entrypoint::h0123456789abcdef"].inclusive_cost,
+        10
+    );
+}
+
+#[test]
+fn resolver_dwarf() {
+    let path = &temp_path("dwarf.so");
+    std::fs::write(path, mock_elf(&[])).unwrap();
+    let resv = crate::resolver::read(Some(path.as_ref()), &Default::default()).unwrap();
+    assert!(!resv.has_source_lines());
+
+    std::fs::write(path, mock_elf(&mock_debug_line())).unwrap();
+    let resv = crate::resolver::read(Some(path.as_ref()), &Default::default()).unwrap();
+    assert!(resv.has_source_lines());
+    assert_eq!(resv.source_line(35), None);
+    assert_eq!(resv.source_line(36), Some(("/src/lib.rs", 10)));
+    assert_eq!(resv.source_line(38), Some(("/src/lib.rs", 11)));
+    assert_eq!(resv.source_line(39), Some(("/src/helper.rs", 3)));
+    assert_eq!(resv.source_line(40), Some(("/src/helper.rs", 3)));
+    assert_eq!(resv.source_line(41), None);
+}
+
+/// Builds debug sections with the line table of `mock_elf`.
+fn mock_debug_line() -> Vec<(&'static str, Vec<u8>)> {
+    use gimli::write::{
+        Address, AttributeValue, DwarfUnit, EndianVec, LineProgram, LineString, Sections,
+    };
+    let encoding = gimli::Encoding {
+        format: gimli::Format::Dwarf32,
+        version: 4,
+        address_size: 8,
+    };
+    let mut program = LineProgram::new(
+        encoding,
+        gimli::LineEncoding::default(),
+        LineString::String(b"/src".to_vec()),
+        LineString::String(b"lib.rs".to_vec()),
+        None,
+    );
+    let dir = program.default_directory();
+    let lib = program.add_file(LineString::String(b"lib.rs".to_vec()), dir, None);
+    let helper = program.add_file(LineString::String(b"helper.rs".to_vec()), dir, None);
+    program.begin_sequence(Some(Address::Constant(0x120)));
+    for (offset, file, line) in [(0, lib, 10), (8, lib, 11), (24, helper, 3)] {
+        program.row().address_offset = offset;
+        program.row().file = file;
+        program.row().line = line;
+        program.generate_row();
+    }
+    program.end_sequence(40);
+
+    let mut dwarf = DwarfUnit::new(encoding);
+    dwarf.unit.line_program = program;
+    let root = dwarf.unit.root();
+    dwarf.unit.get_mut(root).set(
+        gimli::DW_AT_comp_dir,
+        AttributeValue::String(b"/src".to_vec()),
+    );
+    let mut sections = Sections::new(EndianVec::new(gimli::LittleEndian));
+    dwarf.write(&mut sections).unwrap();
+
+    let mut debug = Vec::new();
+    sections
+        .for_each(|id, data| {
+            if !data.slice().is_empty() {
+                debug.push((id.name(), data.slice().to_vec()));
+            }
+            Ok::<_, gimli::write::Error>(())
+        })
+        .unwrap();
+    debug
+}
+
+/// Builds a minimal ELF file with `.text` of two functions, `.symtab`
+/// and the extra (debug) sections.
+fn mock_elf(extra: &[(&str, Vec<u8>)]) -> Vec<u8> {
+    let text: &[u8] = &[
+        0xb7, 0, 0, 0, 0, 0, 0, 0, // mov64 r0, 0x0
+        0x85, 0x10, 0, 0, 1, 0, 0, 0, // call +1
+        0x95, 0, 0, 0, 0, 0, 0, 0, // exit
+        0xbf, 0x10, 0, 0, 0, 0, 0, 0, // mov64 r0, r1
+        0x95, 0, 0, 0, 0, 0, 0, 0, // exit
+    ];
+    let strtab = b"\0_ZN4test10entrypoint17h0123456789abcdefE\0LBB0_1\0helper\0";
+    let text_address = 0x120_u64;
+
+    let mut symtab = vec![0_u8; 24];
+    for (name, info, value) in [
+        (1_u32, 0x12_u8, 0x120_u64),
+        (42, 0x00, 0x130),
+        (49, 0x12, 0x138),
+    ] {
+        symtab.extend(name.to_le_bytes());
+        symtab.extend([info, 0]);
+        symtab.extend(1_u16.to_le_bytes());
+        symtab.extend(value.to_le_bytes());
+        symtab.extend(0_u64.to_le_bytes());
+    }
+
+    // (name, type, flags, address, data, link, info, entsize)
+    let mut sections = vec![
+        ("", 0_u32, 0_u64, 0_u64, Vec::new(), 0_u32, 0_u32, 0_u64),
+        (".text", 1, 6, text_address, text.to_vec(), 0, 0, 0),
+        (".symtab", 2, 0, 0, symtab, 3, 1, 24),
+        (".strtab", 3, 0, 0, strtab.to_vec(), 0, 0, 0),
+    ];
+    for (name, data) in extra {
+        sections.push((name, 1, 0, 0, data.clone(), 0, 0, 0));
+    }
+    let mut shstrtab = vec![0_u8];
+    let mut names = Vec::<u32>::new();
+    for (name, ..) in &sections {
+        names.push(shstrtab.len() as u32);
+        shstrtab.extend(name.as_bytes());
+        shstrtab.push(0);
+    }
+    names.push(shstrtab.len() as u32);
+    shstrtab.extend(b".shstrtab\0");
+    sections.push((".shstrtab", 3, 0, 0, shstrtab, 0, 0, 0));
+
+    let mut offsets = Vec::<u64>::new();
+    let mut data = Vec::<u8>::new();
+    for (.., bytes, _, _, _) in &sections {
+        offsets.push(64 + data.len() as u64);
+        data.extend(bytes);
+    }
+    let sh_offset = (64 + data.len() as u64).div_ceil(8) * 8;
+
+    let mut elf = vec![0x7f, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    elf.extend(3_u16.to_le_bytes()); // e_type: DYN
+    elf.extend(247_u16.to_le_bytes()); // e_machine: BPF
+    elf.extend(1_u32.to_le_bytes());
+    elf.extend(text_address.to_le_bytes()); // e_entry
+    elf.extend(0_u64.to_le_bytes()); // e_phoff
+    elf.extend(sh_offset.to_le_bytes());
+    elf.extend(0_u32.to_le_bytes());
+    let count = sections.len() as u16;
+    for v in [64_u16, 56, 0, 64, count, count - 1] {
+        elf.extend(v.to_le_bytes());
+    }
+    elf.extend(data);
+    elf.resize(sh_offset as usize, 0);
+
+    for (((_, kind, flags, address, bytes, link, info, entsize), name), offset) in
+        sections.iter().zip(names).zip(offsets)
+    {
+        elf.extend(name.to_le_bytes());
+        elf.extend(kind.to_le_bytes());
+        elf.extend(flags.to_le_bytes());
+        elf.extend(address.to_le_bytes());
+        elf.extend(offset.to_le_bytes());
+        elf.extend((bytes.len() as u64).to_le_bytes());
+        elf.extend(link.to_le_bytes());
+        elf.extend(info.to_le_bytes());
+        elf.extend(8_u64.to_le_bytes());
+        elf.extend(entsize.to_le_bytes());
+    }
+    elf
 }
@@ -0,0 +1,73 @@
+//! bpf-profile top command implementation.
+
+use crate::config::Config;
+use crate::error::Result;
+use crate::gen::{self, Summary, Totals};
+use crate::global;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Reads the trace and prints the most expensive functions.
+pub fn run(
+    trace_path: &Path,
+    dump_path: Option<&Path>,
+    config: &Config,
+    limit: usize,
+    lenient: bool,
+) -> Result<()> {
+    let summary = gen::summarize(trace_path, dump_path, config, lenient)?;
+    write_table(io::stdout(), &summary, limit)
+}
+
+/// Writes the table of at most `limit` functions sorted by their self costs,
+/// with inclusive costs, numbers of calls, shares of the total cost
+/// and average inclusive costs per call.
+pub fn write_table(mut output: impl Write, summary: &Summary, limit: usize) -> Result<()> {
+    if global::verbose() {
+        tracing::info!("Sorting functions by costs...")
+    }
+
+    let mut functions = summary.functions.iter().collect::<Vec<_>>();
+    functions.sort_by(|(f1, t1), (f2, t2)| {
+        let key = |t: &Totals| (t.self_cost, t.inclusive_cost);
+        key(t2).cmp(&key(t1)).then(f1.cmp(f2))
+    });
+
+    let percent = |cost| {
+        if summary.total_cost == 0 {
+            0.0
+        } else {
+            cost as f64 * 100.0 / summary.total_cost as f64
+        }
+    };
+
+    writeln!(output, "Total instructions: {}", summary.total_cost)?;
+    writeln!(
+        output,
+        "{:>10} {:>7} {:>10} {:>7} {:>8} {:>10}  Function",
+        "Self", "Self%", "Inclusive", "Incl%", "Calls", "Per call"
+    )?;
+    for (f, t) in functions.iter().take(limit) {
+        let per_call = match t.calls {
+            0 => "-".to_string(),
+            calls => format!("{:.1}", t.inclusive_cost as f64 / calls as f64),
+        };
+        writeln!(
+            output,
+            "{:>10} {:>6.2}% {:>10} {:>6.2}% {:>8} {:>10}  {}",
+            t.self_cost,
+            percent(t.self_cost),
+            t.inclusive_cost,
+            percent(t.inclusive_cost),
+            t.calls,
+            per_call,
+            f
+        )?;
+    }
+    if functions.len() > limit {
+        writeln!(output, "... {} more functions", functions.len() - limit)?;
+    }
+
+    output.flush()?;
+    Ok(())
+}
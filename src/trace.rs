@@ -1,21 +1,427 @@
 //! bpf-profile trace module.
 
-use crate::{error::Result, filebuf};
-use std::io::BufRead;
+use crate::error::{Error, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead, Read};
 
 const HEADER: &str = "BPF Program Instruction Trace";
 
-/// Checks the trace file contains expected header line.
-pub fn contains_standard_header(mut reader: impl BufRead) -> Result<bool> {
-    let mut line = String::with_capacity(512);
-    let mut bytes_read = usize::MAX;
+/// Maximum number of lines kept aside for sections which are not being read.
+const MAX_PENDING_LINES: usize = 1_000_000;
 
-    while bytes_read != 0 {
-        bytes_read = filebuf::read_line(&mut reader, &mut line)?;
-        if line.contains(HEADER) {
-            return Ok(true);
+/// Identifies a stream of the log: the thread name or id which follows the target
+/// in the prefix of a log line. Empty for lines logged without it.
+type Key = String;
+
+/// Represents a line of the log which belongs to a stream not being read now.
+enum Line {
+    Header,
+    Text(String),
+}
+
+/// Splits the trace log into sections, one per traced program invocation.
+/// Each section starts after a standard header line and lasts until the next one.
+///
+/// The log of a validator can interleave output of several threads,
+/// so lines are separated into streams by the thread of their log prefix.
+/// A line without a prefix continues the stream of the last prefixed line.
+/// A section takes lines of the stream of its header only,
+/// lines of other sections are kept aside (up to a limit) until those are read.
+///
+/// The default log format of Solana (`[<timestamp> <level> <target>]`) has no thread,
+/// so all of its lines are one stream and its sections must not be interleaved
+/// (the walker checks instruction counters to detect that).
+pub struct Sections<R> {
+    reader: R,
+    raw: Vec<u8>,
+    line: String,
+    pos: usize,
+    number: usize,
+    current: Option<Key>,
+    last_key: Key,
+    pending: HashMap<Key, VecDeque<Line>>,
+    pending_lines: usize,
+    pending_limit: usize,
+    order: VecDeque<Key>,
+}
+
+impl<R: BufRead> Sections<R> {
+    /// Creates new instance of Sections.
+    pub fn new(reader: R) -> Self {
+        Sections::with_pending_limit(reader, MAX_PENDING_LINES)
+    }
+
+    /// Creates new instance of Sections which keeps at most `limit` lines aside.
+    pub(crate) fn with_pending_limit(reader: R, limit: usize) -> Self {
+        Sections {
+            reader,
+            raw: Vec::with_capacity(512),
+            line: String::with_capacity(512),
+            pos: 0,
+            number: 0,
+            current: None,
+            last_key: Key::new(),
+            pending: HashMap::new(),
+            pending_lines: 0,
+            pending_limit: limit,
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Skips to the next header and returns the section which follows it (if any).
+    pub fn next_section(&mut self) -> Result<Option<Section<'_, R>>> {
+        // Skip the rest of the previous section
+        while self.read_line()? {}
+
+        let key = loop {
+            if let Some(key) = self.order.pop_front() {
+                break key;
+            }
+            match self.read_raw()? {
+                Some((key, line)) => self.put_aside(key, line)?,
+                None => return Ok(None),
+            }
+        };
+        if let Some(queue) = self.pending.get_mut(&key) {
+            let header = queue.pop_front();
+            debug_assert!(matches!(header, Some(Line::Header)));
+            self.pending_lines -= 1;
+        }
+        self.current = Some(key);
+        self.number += 1;
+        Ok(Some(Section { sections: self }))
+    }
+
+    /// Returns number of sections found so far.
+    pub fn count(&self) -> usize {
+        self.number
+    }
+
+    /// Reads next line of the current section.
+    /// Returns false at the end of the section.
+    fn read_line(&mut self) -> io::Result<bool> {
+        self.pos = 0;
+        self.line.clear();
+        let current = match &self.current {
+            Some(key) => key.clone(),
+            None => return Ok(false),
+        };
+        loop {
+            if let Some(queue) = self.pending.get_mut(&current) {
+                match queue.pop_front() {
+                    Some(Line::Text(text)) => {
+                        self.pending_lines -= 1;
+                        self.line = text;
+                        return Ok(true);
+                    }
+                    Some(Line::Header) => {
+                        // Next section of the same stream, keep it for later
+                        queue.push_front(Line::Header);
+                        break;
+                    }
+                    None => {
+                        self.pending.remove(&current);
+                    }
+                }
+            }
+            match self.read_raw()? {
+                Some((key, Line::Text(text))) if key == current => {
+                    self.line = text;
+                    return Ok(true);
+                }
+                Some((key, line)) => self.put_aside(key, line)?,
+                None => break,
+            }
+        }
+        self.current = None;
+        Ok(false)
+    }
+
+    /// Reads next line of the log, splitting off its prefix.
+    /// Corrupted bytes (invalid UTF-8) are replaced, so they do not stop reading.
+    /// Returns None at the end of input.
+    fn read_raw(&mut self) -> io::Result<Option<(Key, Line)>> {
+        lazy_static! {
+            static ref PREFIX: Regex = Regex::new(concat!(
+                r"^\[[^\s\[\]]+\s+[^\s\[\]]+", // [<timestamp> <level>
+                r"(?:\s+[^\s\[\]]+)?",          // <target>
+                r"((?:\s+[^\s\[\]]+)*)\s*\]\s?", // <thread>...]
+            ))
+            .expect("Invalid regex");
+        }
+
+        self.raw.clear();
+        if self.reader.read_until(b'\n', &mut self.raw)? == 0 {
+            return Ok(None);
+        }
+        let raw = String::from_utf8_lossy(&self.raw);
+        let mut text = raw.as_ref();
+        if let Some(caps) = PREFIX.captures(text) {
+            self.last_key = caps[1].split_whitespace().collect::<Vec<_>>().join(" ");
+            text = &text[caps[0].len()..];
+        }
+        let line = if text.contains(HEADER) {
+            Line::Header
+        } else {
+            Line::Text(text.to_string())
+        };
+        Ok(Some((self.last_key.clone(), line)))
+    }
+
+    /// Keeps a line of another stream for its section.
+    /// Drops the line if no section of the stream is pending.
+    /// Returns an error if too many lines are kept aside already.
+    fn put_aside(&mut self, key: Key, line: Line) -> io::Result<()> {
+        let is_header = matches!(line, Line::Header);
+        if !is_header && !self.pending.contains_key(&key) {
+            return Ok(());
+        }
+        if self.pending_lines >= self.pending_limit {
+            let err = Error::TracePending(self.pending_limit);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+        }
+        if is_header {
+            self.order.push_back(key.clone());
+        }
+        self.pending.entry(key).or_default().push_back(line);
+        self.pending_lines += 1;
+        Ok(())
+    }
+}
+
+/// Represents one section of the trace log: a reader of the lines below the header.
+pub struct Section<'a, R> {
+    sections: &'a mut Sections<R>,
+}
+
+impl<R> Section<'_, R> {
+    /// Returns ordinal number of the section (starting from 1).
+    pub fn number(&self) -> usize {
+        self.sections.number
+    }
+}
+
+impl<R: BufRead> Read for Section<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = std::cmp::min(available.len(), buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for Section<'_, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let s = &mut *self.sections;
+        if s.pos >= s.line.len() {
+            s.read_line()?;
         }
+        Ok(&s.line.as_bytes()[s.pos..])
     }
 
-    Ok(false)
+    fn consume(&mut self, amt: usize) {
+        self.sections.pos += amt;
+    }
+}
+
+/// Reads all sections of the log in order, passing each one to `f`.
+/// Returns an error if the log has no sections.
+pub(crate) fn for_each_section<R: BufRead>(
+    reader: R,
+    mut f: impl FnMut(Section<'_, R>) -> Result<()>,
+) -> Result<()> {
+    let mut sections = Sections::new(reader);
+    while let Some(section) = sections.next_section()? {
+        f(section)?;
+    }
+    if sections.count() == 0 {
+        return Err(Error::TraceFormat);
+    }
+    Ok(())
+}
+
+use crate::bpf::Instruction;
+use crate::config::{Address, Config, ProgramCounter};
+use crate::error::recover;
+use std::sync::Arc;
+
+/// Represents a step of the traced execution of a program.
+#[derive(Debug)]
+pub(crate) enum Step {
+    /// An executed instruction (including calls and exits) at line `lc`.
+    Instruction { ix: Instruction, lc: usize },
+    /// A call of a function at line `lc`, followed by the first instruction
+    /// of the callee.
+    Call {
+        caller_pc: ProgramCounter,
+        address: Address,
+        first: Instruction,
+        lc: usize,
+    },
+    /// A call of a syscall at line `lc`, which has no traced instructions.
+    Syscall {
+        caller_pc: ProgramCounter,
+        address: Address,
+        name: String,
+        lc: usize,
+    },
+    /// An exit of a function at line `lc`.
+    Exit { lc: usize },
+}
+
+/// Walks a section of the trace line by line, yielding steps of the execution.
+/// Every instruction is followed by the step of its call or exit (if any).
+/// Lines which are not instructions are skipped.
+/// In the lenient mode malformed instructions and calls are logged and skipped:
+/// the call instruction is left as a plain one, and the line which follows it
+/// is processed as usual. A call at the end of a truncated section is left
+/// as a plain instruction too.
+/// Instruction counters (the first column) of a section are consecutive,
+/// a gap means that lines of another section are mixed in (see `Sections`).
+pub(crate) struct Walker<R> {
+    reader: R,
+    line: String,
+    lc: usize,
+    counter: Option<usize>,
+    config: Arc<Config>,
+    lenient: bool,
+    ended: bool,
+    callee: Option<(Instruction, usize)>,
+    pending: Option<Step>,
+}
+
+impl<R: BufRead> Walker<R> {
+    /// Creates new instance of Walker, which knows syscalls by the settings.
+    pub(crate) fn new(reader: R, config: Arc<Config>, lenient: bool) -> Self {
+        Walker {
+            reader,
+            line: String::with_capacity(512),
+            lc: 0,
+            counter: None,
+            config,
+            lenient,
+            ended: false,
+            callee: None,
+            pending: None,
+        }
+    }
+
+    /// Returns number of lines read so far.
+    pub(crate) fn lines(&self) -> usize {
+        self.lc
+    }
+
+    /// Returns the next step of the execution (None at the end of the section).
+    fn step(&mut self) -> Result<Option<Step>> {
+        if let Some(step) = self.pending.take() {
+            return Ok(Some(step));
+        }
+        let (ix, lc) = match self.callee.take() {
+            Some(callee) => callee,
+            None => match self.read_instruction()? {
+                Some(next) => next,
+                None => return Ok(None),
+            },
+        };
+        if ix.is_exit() {
+            self.pending = Some(Step::Exit { lc });
+        } else if ix.is_call() {
+            self.pending = self.call(&ix, lc)?;
+        }
+        Ok(Some(Step::Instruction { ix, lc }))
+    }
+
+    /// Reads lines up to the next instruction.
+    fn read_instruction(&mut self) -> Result<Option<(Instruction, usize)>> {
+        loop {
+            if !self.read_line()? {
+                return Ok(None);
+            }
+            let ixr = Instruction::parse(&self.line, self.lc);
+            if let Err(Error::TraceSkipped) = &ixr {
+                continue;
+            }
+            if let Some(ix) = recover(ixr, self.lenient)? {
+                return Ok(Some((ix, self.lc)));
+            }
+        }
+    }
+
+    /// Returns the step of the call instruction at line `lc`.
+    /// Reads the first instruction of the callee, which is the next step
+    /// of the execution unless the callee is a syscall.
+    fn call(&mut self, ix: &Instruction, lc: usize) -> Result<Option<Step>> {
+        if let Some(name) = ix.syscall(&self.config) {
+            let address = recover(ix.extract_call_target(lc), self.lenient)?;
+            return Ok(address.map(|address| Step::Syscall {
+                caller_pc: ix.pc(),
+                address,
+                name,
+                lc,
+            }));
+        }
+
+        if !self.read_line()? {
+            return Ok(None);
+        }
+        let callee = match Instruction::parse(&self.line, self.lc) {
+            Err(Error::TraceSkipped) => Err(Error::TraceNoCallee(ix.text(), lc)),
+            result => result,
+        };
+        let first = match recover(callee, self.lenient)? {
+            Some(first) => first,
+            None => return Ok(None),
+        };
+        self.callee = Some((first.clone(), self.lc));
+        let address = recover(ix.call_target(&first, lc), self.lenient)?;
+        Ok(address.map(|address| Step::Call {
+            caller_pc: ix.pc(),
+            address,
+            first,
+            lc,
+        }))
+    }
+
+    /// Reads the next line. Returns false at the end of the section.
+    fn read_line(&mut self) -> Result<bool> {
+        if self.ended {
+            return Ok(false);
+        }
+        self.line.clear();
+        let bytes_read = crate::filebuf::read_line(&mut self.reader, &mut self.line)?;
+        self.lc += 1;
+        self.ended = bytes_read == 0;
+        if !self.ended {
+            recover(self.check_counter(), self.lenient)?;
+        }
+        Ok(!self.ended)
+    }
+
+    /// Checks that the instruction counter of the line follows the previous one.
+    /// Lines which are not instructions are not checked.
+    fn check_counter(&mut self) -> Result<()> {
+        let mut columns = self.line.split_whitespace();
+        let counter = match (columns.next(), columns.next()) {
+            (Some(counter), Some(regs)) if regs.starts_with('[') => counter.parse().ok(),
+            _ => None,
+        };
+        let previous = std::mem::replace(&mut self.counter, counter);
+        match (previous, counter) {
+            (Some(previous), Some(counter)) if counter != previous + 1 => Err(
+                Error::TraceInterleaved(self.line.trim().to_string(), self.lc),
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Walker<R> {
+    type Item = Result<Step>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.step().transpose()
+    }
 }